@@ -0,0 +1,80 @@
+//! Derive macros for `webgl-wrapper`. These are re-exported by `webgl-wrapper`, so this crate
+//! shouldn't be used directly.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields};
+
+/// Implements `Std140` for a struct with named fields, each of which must implement `Std140`.
+///
+/// The fields are laid out in declaration order following the std140 rules: each field is
+/// aligned to its own `ALIGN`, and the struct is aligned to the largest of those (at least 16
+/// bytes), with padding at the end to make its size a multiple of that.
+#[proc_macro_derive(Std140)]
+pub fn derive_std140(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    if !input.generics.params.is_empty() {
+        return syn::Error::new_spanned(
+            &input.generics,
+            "Std140 can't be derived for generic types",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return syn::Error::new_spanned(
+                    name,
+                    "Std140 can only be derived for structs with named fields",
+                )
+                .to_compile_error()
+                .into();
+            }
+        },
+        _ => {
+            return syn::Error::new_spanned(name, "Std140 can only be derived for structs")
+                .to_compile_error()
+                .into();
+        }
+    };
+
+    let field_names: Vec<_> = fields.iter().map(|field| &field.ident).collect();
+    let field_types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+
+    let res = quote! {
+        impl ::webgl_wrapper::Std140 for #name {
+            const ALIGN: usize = {
+                let mut align = 16;
+                #(
+                    if <#field_types as ::webgl_wrapper::Std140>::ALIGN > align {
+                        align = <#field_types as ::webgl_wrapper::Std140>::ALIGN;
+                    }
+                )*
+                align
+            };
+
+            const SIZE: usize = {
+                let mut offset = 0;
+                #(
+                    offset = ::webgl_wrapper::std140_align_to(
+                        offset,
+                        <#field_types as ::webgl_wrapper::Std140>::ALIGN,
+                    ) + <#field_types as ::webgl_wrapper::Std140>::SIZE;
+                )*
+                ::webgl_wrapper::std140_align_to(offset, <Self as ::webgl_wrapper::Std140>::ALIGN)
+            };
+
+            fn write_std140(&self, out: &mut Vec<u8>) {
+                ::webgl_wrapper::std140_pad(out, <Self as ::webgl_wrapper::Std140>::ALIGN);
+                let start = out.len();
+                #(::webgl_wrapper::Std140::write_std140(&self.#field_names, out);)*
+                out.resize(start + <Self as ::webgl_wrapper::Std140>::SIZE, 0);
+            }
+        }
+    };
+    res.into()
+}
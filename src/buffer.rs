@@ -0,0 +1,187 @@
+use std::mem;
+use std::slice;
+use web_sys::*;
+
+use crate::context::*;
+use crate::mesh::*;
+
+/// A GL buffer whose capacity is tracked separately from the length of its contents, so it can
+/// be partially updated with `bufferSubData` and grown without reallocating on every write.
+pub(crate) struct GlBuffer {
+    pub(crate) buffer: WebGlBuffer,
+    target: u32,
+    usage: MeshUsage,
+    // Both in bytes
+    len: usize,
+    capacity: usize,
+    context: GlContext,
+}
+
+impl Drop for GlBuffer {
+    fn drop(&mut self) {
+        self.context.inner.delete_buffer(Some(&self.buffer));
+    }
+}
+
+impl GlBuffer {
+    /// Creates an empty buffer. It isn't bound, since binding an `ELEMENT_ARRAY_BUFFER` changes
+    /// the current VAO.
+    pub fn new(context: &GlContext, target: u32, usage: MeshUsage) -> Self {
+        GlBuffer {
+            buffer: context.inner.create_buffer().unwrap(),
+            target,
+            usage,
+            len: 0,
+            capacity: 0,
+            context: context.clone(),
+        }
+    }
+
+    pub fn bind(&self) {
+        self.context.inner.bind_buffer(self.target, Some(&self.buffer));
+    }
+
+    /// The length of the buffer's contents, in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The size of the buffer's storage, in bytes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the usage hint. The storage is reallocated with the new usage on the next write.
+    pub fn set_usage(&mut self, usage: MeshUsage) {
+        if usage != self.usage {
+            self.usage = usage;
+            self.capacity = 0;
+        }
+    }
+
+    /// Replaces the buffer's contents. The existing storage is reused if it's large enough.
+    pub fn set_data(&mut self, data: &[u8]) {
+        self.bind();
+        if data.len() > self.capacity {
+            self.capacity = self.grown_capacity(data.len());
+            self.context.inner.buffer_data_with_i32(
+                self.target,
+                self.capacity as i32,
+                self.usage.as_gl(),
+            );
+        }
+        self.context.inner.buffer_sub_data_with_i32_and_u8_array(self.target, 0, data);
+        self.len = data.len();
+    }
+
+    /// Writes `data` starting at byte `offset`, which must be at most the current length. The
+    /// buffer is extended if the data goes past the end, keeping the existing contents. For an
+    /// `ELEMENT_ARRAY_BUFFER`, the VAO that uses the buffer must be bound.
+    pub fn write(&mut self, offset: usize, data: &[u8]) {
+        assert!(offset <= self.len, "Write at {} is past the end of the buffer", offset);
+        let end = offset + data.len();
+        if end > self.capacity {
+            self.grow(end);
+        }
+        self.bind();
+        self.context.inner.buffer_sub_data_with_i32_and_u8_array(self.target, offset as i32, data);
+        self.len = self.len.max(end);
+    }
+
+    /// Re-specifies the buffer's storage without keeping its contents, growing it if it's
+    /// smaller than `min_capacity` bytes. The driver can give the buffer new memory rather than
+    /// waiting for the GPU to finish with the old contents.
+    pub fn orphan(&mut self, min_capacity: usize) {
+        if min_capacity > self.capacity {
+            self.capacity = self.grown_capacity(min_capacity);
+        }
+        self.bind();
+        self.context.inner.buffer_data_with_i32(
+            self.target,
+            self.capacity as i32,
+            self.usage.as_gl(),
+        );
+        self.len = 0;
+    }
+
+    /// Writes `data` after the current contents, at an offset that's a multiple of 4 bytes, and
+    /// returns the offset. If there isn't enough room left, the buffer is orphaned and the data
+    /// is written at the start, so the buffer can be used as a ring buffer for data that's only
+    /// drawn once.
+    pub fn append(&mut self, data: &[u8]) -> usize {
+        // 4 bytes is the largest alignment that a vertex attribute or index needs
+        let mut offset = (self.len + 3) & !3;
+        if offset + data.len() > self.capacity {
+            self.orphan(data.len());
+            offset = 0;
+        } else {
+            self.bind();
+        }
+        self.context.inner.buffer_sub_data_with_i32_and_u8_array(self.target, offset as i32, data);
+        self.len = offset + data.len();
+        offset
+    }
+
+    /// Shortens the contents to `len` bytes, keeping the storage.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// Doubles the capacity, or more if needed to hold `min_capacity` bytes.
+    fn grown_capacity(&self, min_capacity: usize) -> usize {
+        min_capacity.max(self.capacity * 2)
+    }
+
+    /// Reallocates the buffer's storage to hold at least `min_capacity` bytes, keeping its
+    /// contents. The same `WebGlBuffer` is kept, so VAOs that refer to it stay valid; the
+    /// contents are copied to a temporary buffer and back.
+    ///
+    /// WebGL only allows copies between buffers of the same type, which is set by the first
+    /// target a buffer is bound to, so the temporary buffer is first bound to `target`. For an
+    /// `ELEMENT_ARRAY_BUFFER`, that replaces the current VAO's index buffer until this buffer is
+    /// bound again below, so the VAO that uses this buffer must be bound.
+    fn grow(&mut self, min_capacity: usize) {
+        let inner = &self.context.inner;
+        self.capacity = self.grown_capacity(min_capacity);
+
+        let temp = if self.len > 0 {
+            let temp = inner.create_buffer().unwrap();
+            inner.bind_buffer(self.target, Some(&temp));
+            inner.buffer_data_with_i32(self.target, self.len as i32, WebGl2::STREAM_COPY);
+            inner.bind_buffer(WebGl2::COPY_READ_BUFFER, Some(&self.buffer));
+            inner.copy_buffer_sub_data_with_i32_and_i32_and_i32(
+                WebGl2::COPY_READ_BUFFER,
+                self.target,
+                0,
+                0,
+                self.len as i32,
+            );
+            Some(temp)
+        } else {
+            None
+        };
+
+        self.bind();
+        inner.buffer_data_with_i32(self.target, self.capacity as i32, self.usage.as_gl());
+
+        if let Some(temp) = temp {
+            inner.bind_buffer(WebGl2::COPY_READ_BUFFER, Some(&temp));
+            inner.copy_buffer_sub_data_with_i32_and_i32_and_i32(
+                WebGl2::COPY_READ_BUFFER,
+                self.target,
+                0,
+                0,
+                self.len as i32,
+            );
+            inner.bind_buffer(WebGl2::COPY_READ_BUFFER, None);
+            inner.delete_buffer(Some(&temp));
+        }
+    }
+}
+
+/// Returns the bytes of a slice of `#[repr(C)]` vertex or instance data.
+///
+/// This is unsafe because `T` must not contain padding or any other uninitialized bytes.
+pub(crate) unsafe fn slice_as_bytes<T>(data: &[T]) -> &[u8] {
+    slice::from_raw_parts(data.as_ptr() as *const u8, mem::size_of_val(data))
+}
@@ -0,0 +1,534 @@
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Generates Rust bindings for a vertex and fragment shader, for use in a build script. This
+/// requires the `codegen` feature.
+///
+/// `name` is used as a prefix for the generated types. For a name of `Sprite`, these are:
+/// * `SpriteVertex`, with a field for each `in` variable of the vertex shader.
+/// * `SpriteUniforms` and `SpriteUniformsGl`, with a field for each uniform outside a uniform
+///   block in either shader. Samplers become `&Texture2d` fields, and arrays become Rust
+///   arrays. Since the compiler can remove unused uniforms, `OptionalUniform` is used for them.
+/// * An `EmbeddedShaders` impl for `SpriteUniformsGl`, which embeds the shaders with
+///   `include_str!` so `GlProgram::<SpriteVertex, SpriteUniformsGl>::new_embedded` can create
+///   the program.
+///
+/// The generated code uses the `cgmath` and `web_sys` crates, which must be dependencies of the
+/// crate that includes it. Only declarations that the shaders make outside of functions are
+/// used; `#define`s and `#if`s aren't evaluated, and array sizes must be integer literals.
+///
+/// This also tells cargo to rerun the build script when either shader changes.
+///
+/// Example usage:
+/// ```
+/// // build.rs
+/// fn main() {
+///     let bindings = webgl_wrapper::generate_bindings(
+///         "Sprite",
+///         "shaders/sprite.vert",
+///         "shaders/sprite.frag",
+///     )
+///     .unwrap();
+///     let out_dir = std::env::var("OUT_DIR").unwrap();
+///     std::fs::write(format!("{}/sprite.rs", out_dir), bindings).unwrap();
+/// }
+///
+/// // main.rs
+/// include!(concat!(env!("OUT_DIR"), "/sprite.rs"));
+///
+/// let program = GlProgram::<SpriteVertex, SpriteUniformsGl>::new_embedded(&context);
+/// ```
+pub fn generate_bindings(
+    name: &str,
+    vert_shader_path: impl AsRef<Path>,
+    frag_shader_path: impl AsRef<Path>,
+) -> Result<String, String> {
+    let read = |path: &Path| {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        let path = path
+            .canonicalize()
+            .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        Ok::<_, String>((path.display().to_string(), source))
+    };
+    let (vert_shader_path, vert_shader) = read(vert_shader_path.as_ref())?;
+    let (frag_shader_path, frag_shader) = read(frag_shader_path.as_ref())?;
+
+    let attributes = global_declarations(&vert_shader, "in")
+        .into_iter()
+        .map(|decl| {
+            if decl.array_len.is_some() {
+                return Err(format!("Vertex attribute {} can't be an array", decl.name));
+            }
+            let (rust_type, attribute_type, component_type, conversion) = attribute_type(&decl.ty)
+                .ok_or_else(|| {
+                    format!("Vertex attribute {} has unsupported type {}", decl.name, decl.ty)
+                })?;
+            Ok((decl.name, rust_type, attribute_type, component_type, conversion))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut uniforms: Vec<(String, UniformKind)> = vec![];
+    for decl in global_declarations(&vert_shader, "uniform")
+        .into_iter()
+        .chain(global_declarations(&frag_shader, "uniform"))
+    {
+        let mut kind = uniform_type(&decl.ty)
+            .ok_or_else(|| format!("Uniform {} has unsupported type {}", decl.name, decl.ty))?;
+        if let Some(len) = &decl.array_len {
+            if len.parse::<usize>().is_err() {
+                return Err(format!("Uniform {} must have an integer literal size", decl.name));
+            }
+            kind = match kind {
+                UniformKind::Value(ty) => UniformKind::Array(ty, len.clone()),
+                _ => return Err(format!("Uniform {} can't be an array", decl.name)),
+            };
+        }
+        match uniforms.iter().find(|(name, _)| *name == decl.name) {
+            Some((_, existing)) if *existing != kind => {
+                return Err(format!("Uniform {} has different types in each shader", decl.name))
+            }
+            Some(_) => {}
+            None => uniforms.push((decl.name, kind)),
+        }
+    }
+
+    let has_textures = uniforms.iter().any(|(_, kind)| *kind == UniformKind::Texture);
+    let lifetime = if has_textures { "<'a>" } else { "" };
+    let ww = "::webgl_wrapper";
+
+    // Writing to a `String` can't fail, so the results are ignored.
+    let mut res = String::new();
+    let _ = writeln!(
+        res,
+        "// Generated by webgl_wrapper::generate_bindings from {} and {}. Do not edit.\n",
+        vert_shader_path, frag_shader_path
+    );
+
+    let _ = writeln!(res, "#[derive(Copy, Clone, Debug)]");
+    let _ = writeln!(res, "pub struct {}Vertex {{", name);
+    for (attr_name, rust_type, ..) in &attributes {
+        let _ = writeln!(res, "    pub {}: {},", attr_name, rust_type);
+    }
+    let _ = writeln!(res, "}}\n");
+
+    let _ = writeln!(res, "impl {}::VertexData for {}Vertex {{", ww, name);
+    let _ = writeln!(res, "    const ATTRIBUTES: {}::Attributes = &[", ww);
+    for (attr_name, _, attribute_type, component_type, conversion) in &attributes {
+        let _ = writeln!(
+            res,
+            "        {ww}::Attribute::new({:?}, {ww}::AttributeType::{}, \
+             {ww}::ComponentType::{}, {ww}::AttributeConversion::{}),",
+            attr_name,
+            attribute_type,
+            component_type,
+            conversion,
+            ww = ww
+        );
+    }
+    let _ = writeln!(res, "    ];\n}}\n");
+
+    let _ = writeln!(res, "impl {}::VertexComponent for {}Vertex {{", ww, name);
+    let _ = writeln!(res, "    fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {{");
+    for (attr_name, ..) in &attributes {
+        let _ =
+            writeln!(res, "        {}::VertexComponent::add_to_mesh(&self.{}, f);", ww, attr_name);
+    }
+    let _ = writeln!(res, "    }}\n}}\n");
+
+    let _ = writeln!(res, "pub struct {}Uniforms{} {{", name, lifetime);
+    for (uniform_name, kind) in &uniforms {
+        let ty = match kind {
+            UniformKind::Value(ty) => ty.clone(),
+            UniformKind::Array(ty, len) => format!("[{}; {}]", ty, len),
+            UniformKind::Texture => format!("&'a {}::Texture2d", ww),
+        };
+        let _ = writeln!(res, "    pub {}: {},", uniform_name, ty);
+    }
+    let _ = writeln!(res, "}}\n");
+
+    let _ = writeln!(res, "pub struct {}UniformsGl {{", name);
+    for (uniform_name, kind) in &uniforms {
+        let ty = match kind {
+            UniformKind::Value(ty) => format!("{}::uniforms::OptionalUniform<{}>", ww, ty),
+            UniformKind::Array(ty, _) => format!("{}::uniforms::OptionalUniform<[{}]>", ww, ty),
+            UniformKind::Texture => format!("{}::uniforms::OptionalTextureUniform", ww),
+        };
+        let _ = writeln!(res, "    pub {}: {},", uniform_name, ty);
+    }
+    let _ = writeln!(res, "}}\n");
+
+    let _ = writeln!(res, "impl{} {}::Uniforms for {}Uniforms{} {{", lifetime, ww, name, lifetime);
+    let _ = writeln!(res, "    type GlUniforms = {}UniformsGl;\n", name);
+    let _ = writeln!(res, "    #[allow(unused_variables)]");
+    let _ = writeln!(
+        res,
+        "    fn update(&self, program: &{}::BoundProgram<'_, {}UniformsGl>) {{",
+        ww, name
+    );
+    let _ = writeln!(res, "        let gl_uniforms = program.uniforms();");
+    for (uniform_name, kind) in &uniforms {
+        let value = match kind {
+            UniformKind::Value(_) => format!("&self.{}", uniform_name),
+            UniformKind::Array(..) => format!("&self.{}[..]", uniform_name),
+            UniformKind::Texture => format!("self.{}", uniform_name),
+        };
+        let _ = writeln!(res, "        gl_uniforms.{}.set(program, {});", uniform_name, value);
+    }
+    let _ = writeln!(res, "    }}\n}}\n");
+
+    let _ = writeln!(res, "impl {}::GlUniforms for {}UniformsGl {{", ww, name);
+    let _ = writeln!(res, "    #[allow(unused_variables)]");
+    let _ = writeln!(
+        res,
+        "    fn new(context: &{}::GlContext, program: &::web_sys::WebGlProgram) -> Self {{",
+        ww
+    );
+    let _ = writeln!(res, "        Self {{");
+    for (uniform_name, kind) in &uniforms {
+        let ty = match kind {
+            UniformKind::Texture => "OptionalTextureUniform",
+            _ => "OptionalUniform",
+        };
+        let _ = writeln!(
+            res,
+            "            {}: {}::uniforms::{}::new({:?}, context, program),",
+            uniform_name, ww, ty, uniform_name
+        );
+    }
+    let _ = writeln!(res, "        }}\n    }}\n}}\n");
+
+    let _ = writeln!(res, "impl {}::EmbeddedShaders for {}UniformsGl {{", ww, name);
+    let _ = writeln!(res, "    type Vertex = {}Vertex;", name);
+    let _ = writeln!(
+        res,
+        "    const VERT_SHADER: &'static str = include_str!({:?});",
+        vert_shader_path
+    );
+    let _ = writeln!(
+        res,
+        "    const FRAG_SHADER: &'static str = include_str!({:?});",
+        frag_shader_path
+    );
+    let _ = writeln!(res, "}}");
+
+    Ok(res)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum UniformKind {
+    Value(String),
+    Array(String, String),
+    Texture,
+}
+
+/// A variable declared outside of any function or block.
+struct Declaration {
+    name: String,
+    ty: String,
+    array_len: Option<String>,
+}
+
+/// Returns the global variables declared with the given storage qualifier (`in` or `uniform`).
+/// Uniform blocks are skipped.
+fn global_declarations(source: &str, storage_qualifier: &str) -> Vec<Declaration> {
+    let ignored_qualifiers =
+        ["flat", "smooth", "centroid", "invariant", "lowp", "mediump", "highp", "const"];
+
+    let mut res = vec![];
+    for statement in global_statements(&strip_comments(source)) {
+        let mut statement = statement.trim();
+        if statement.starts_with("layout") {
+            match statement.find(')') {
+                Some(i) => statement = &statement[i + 1..],
+                None => continue,
+            }
+        }
+
+        let mut tokens =
+            statement.split_whitespace().skip_while(|token| ignored_qualifiers.contains(token));
+        if tokens.next() != Some(storage_qualifier) {
+            continue;
+        }
+        let ty = match tokens.find(|token| !ignored_qualifiers.contains(token)) {
+            Some(ty) => ty.to_string(),
+            None => continue,
+        };
+
+        let declarators: String = tokens.collect();
+        for declarator in declarators.split(',') {
+            let (name, array_len) = match declarator.find('[') {
+                Some(i) => {
+                    let len = declarator[i + 1..].trim_end_matches(']');
+                    (&declarator[..i], Some(len.to_string()))
+                }
+                None => (declarator, None),
+            };
+            res.push(Declaration { name: name.to_string(), ty: ty.clone(), array_len });
+        }
+    }
+    res
+}
+
+/// Splits the source into the statements that are outside of any braces. Function definitions
+/// and blocks are skipped, as are preprocessor directives.
+fn global_statements(source: &str) -> Vec<String> {
+    let mut res = vec![];
+    let mut statement = String::new();
+    let mut depth = 0;
+    for line in source.lines().filter(|line| !line.trim_start().starts_with('#')) {
+        for c in line.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    statement.clear();
+                }
+                '}' => depth -= 1,
+                ';' if depth == 0 => {
+                    res.push(statement.clone());
+                    statement.clear();
+                }
+                _ if depth == 0 => statement.push(c),
+                _ => {}
+            }
+        }
+        statement.push(' ');
+    }
+    res
+}
+
+fn strip_comments(source: &str) -> String {
+    let mut res = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(i) = rest.find('/') {
+        res.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("//") {
+            rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+        } else if rest.starts_with("/*") {
+            rest = &rest[rest.find("*/").map_or(rest.len(), |i| i + 2)..];
+            res.push(' ');
+        } else {
+            res.push('/');
+            rest = &rest[1..];
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Returns the Rust type and the `AttributeType`, `ComponentType`, and `AttributeConversion`
+/// variants for a GLSL attribute type.
+fn attribute_type(ty: &str) -> Option<(String, String, &'static str, &'static str)> {
+    let (component_type, conversion, rust_scalar) = match ty.chars().next()? {
+        'i' => ("I32", "Integer", "i32"),
+        'u' => ("U32", "Integer", "u32"),
+        _ => ("F32", "Float", "f32"),
+    };
+    let (rust_type, attribute_type) = match ty {
+        "float" | "int" | "uint" => (rust_scalar.to_string(), "Scalar".to_string()),
+        _ if ty.ends_with("vec2") || ty.ends_with("vec3") || ty.ends_with("vec4") => {
+            if !matches!(&ty[..ty.len() - 4], "" | "i" | "u") {
+                return None;
+            }
+            let n = &ty[ty.len() - 1..];
+            (format!("::cgmath::Vector{}<{}>", n, rust_scalar), format!("Vec{}", n))
+        }
+        _ => {
+            let (columns, rows) = matrix_shape(ty)?;
+            if columns == rows {
+                (format!("::cgmath::Matrix{}<f32>", columns), format!("Mat{}", columns))
+            } else {
+                (format!("[[f32; {}]; {}]", rows, columns), format!("Mat{}x{}", columns, rows))
+            }
+        }
+    };
+    Some((rust_type, attribute_type, component_type, conversion))
+}
+
+fn uniform_type(ty: &str) -> Option<UniformKind> {
+    let rust_type = match ty {
+        "sampler2D" => return Some(UniformKind::Texture),
+        "float" => "f32".to_string(),
+        "int" => "i32".to_string(),
+        "uint" => "u32".to_string(),
+        "bool" => "bool".to_string(),
+        _ if ty.ends_with("vec2") || ty.ends_with("vec3") || ty.ends_with("vec4") => {
+            let scalar = match &ty[..ty.len() - 4] {
+                "" => "f32",
+                "i" => "i32",
+                "u" => "u32",
+                "b" => "bool",
+                _ => return None,
+            };
+            format!("::cgmath::Vector{}<{}>", &ty[ty.len() - 1..], scalar)
+        }
+        _ => {
+            let (columns, rows) = matrix_shape(ty)?;
+            if columns == rows {
+                format!("::cgmath::Matrix{}<f32>", columns)
+            } else {
+                format!("::webgl_wrapper::uniforms::Matrix{}x{}", columns, rows)
+            }
+        }
+    };
+    Some(UniformKind::Value(rust_type))
+}
+
+/// Returns the number of columns and rows of a GLSL matrix type such as `mat3` or `mat4x2`.
+fn matrix_shape(ty: &str) -> Option<(usize, usize)> {
+    let shape = ty.strip_prefix("mat")?;
+    let parse = |n: &str| n.parse::<usize>().ok().filter(|n| (2..=4).contains(n));
+    match shape.split_once('x') {
+        Some((columns, rows)) => Some((parse(columns)?, parse(rows)?)),
+        None => parse(shape).map(|n| (n, n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declarations(
+        source: &str,
+        storage_qualifier: &str,
+    ) -> Vec<(String, String, Option<String>)> {
+        global_declarations(source, storage_qualifier)
+            .into_iter()
+            .map(|decl| (decl.name, decl.ty, decl.array_len))
+            .collect()
+    }
+
+    fn decl(name: &str, ty: &str, array_len: Option<&str>) -> (String, String, Option<String>) {
+        (name.to_string(), ty.to_string(), array_len.map(str::to_string))
+    }
+
+    #[test]
+    fn declarations_with_qualifiers() {
+        let source = "\
+#version 300 es
+precision highp float;
+layout(location = 0) in vec2 pos;
+flat in highp int id;
+out vec2 uv;
+uniform mediump float a, b;
+uniform mat4 bones[16];
+const float scale = 2.0;
+";
+        assert_eq!(
+            declarations(source, "in"),
+            [decl("pos", "vec2", None), decl("id", "int", None)]
+        );
+        assert_eq!(
+            declarations(source, "uniform"),
+            [decl("a", "float", None), decl("b", "float", None), decl("bones", "mat4", Some("16"))]
+        );
+    }
+
+    #[test]
+    fn declarations_skip_blocks_and_functions() {
+        let source = "\
+uniform Camera {
+    mat4 view_proj;
+} camera;
+layout(std140) uniform Lights { vec4 color; };
+uniform float time;
+void main() {
+    uniform_value = time;
+}
+";
+        assert_eq!(declarations(source, "uniform"), [decl("time", "float", None)]);
+    }
+
+    #[test]
+    fn statements_skip_directives() {
+        let source =
+            "#version 300 es\n#define N 4\nin vec2 a;\nin\n  vec3 b;\nvoid f() { int x; }\n";
+        let statements: Vec<String> =
+            global_statements(source).iter().map(|s| s.split_whitespace().collect()).collect();
+        assert_eq!(statements, ["invec2a", "invec3b"]);
+    }
+
+    #[test]
+    fn comments_are_stripped() {
+        assert_eq!(
+            strip_comments("in vec2 a; // in vec3 b;\nin vec3 c;"),
+            "in vec2 a; \nin vec3 c;"
+        );
+        assert_eq!(strip_comments("uniform /* float */ vec2 a;"), "uniform   vec2 a;");
+        assert_eq!(strip_comments("a = b / c; /* unclosed"), "a = b / c;  ");
+        let source = "/*\nuniform float hidden;\n*/\nuniform float shown;";
+        assert_eq!(declarations(source, "uniform"), [decl("shown", "float", None)]);
+    }
+
+    #[test]
+    fn attribute_types() {
+        assert_eq!(
+            attribute_type("float"),
+            Some(("f32".to_string(), "Scalar".to_string(), "F32", "Float"))
+        );
+        assert_eq!(
+            attribute_type("uvec3"),
+            Some(("::cgmath::Vector3<u32>".to_string(), "Vec3".to_string(), "U32", "Integer"))
+        );
+        assert_eq!(
+            attribute_type("mat4"),
+            Some(("::cgmath::Matrix4<f32>".to_string(), "Mat4".to_string(), "F32", "Float"))
+        );
+        assert_eq!(
+            attribute_type("mat2x3"),
+            Some(("[[f32; 3]; 2]".to_string(), "Mat2x3".to_string(), "F32", "Float"))
+        );
+        assert_eq!(attribute_type("bvec2"), None);
+        assert_eq!(attribute_type("sampler2D"), None);
+    }
+
+    #[test]
+    fn uniform_types() {
+        let value = |ty: &str| Some(UniformKind::Value(ty.to_string()));
+        assert_eq!(uniform_type("sampler2D"), Some(UniformKind::Texture));
+        assert_eq!(uniform_type("bool"), value("bool"));
+        assert_eq!(uniform_type("bvec3"), value("::cgmath::Vector3<bool>"));
+        assert_eq!(uniform_type("ivec4"), value("::cgmath::Vector4<i32>"));
+        assert_eq!(uniform_type("mat3"), value("::cgmath::Matrix3<f32>"));
+        assert_eq!(uniform_type("mat4x2"), value("::webgl_wrapper::uniforms::Matrix4x2"));
+        assert_eq!(uniform_type("dvec2"), None);
+        assert_eq!(uniform_type("samplerCube"), None);
+    }
+
+    #[test]
+    fn matrix_shapes() {
+        assert_eq!(matrix_shape("mat2"), Some((2, 2)));
+        assert_eq!(matrix_shape("mat3x4"), Some((3, 4)));
+        assert_eq!(matrix_shape("mat4x2"), Some((4, 2)));
+        assert_eq!(matrix_shape("mat5"), None);
+        assert_eq!(matrix_shape("mat2x1"), None);
+        assert_eq!(matrix_shape("vec2"), None);
+    }
+
+    #[test]
+    fn uniforms_must_match_between_shaders() {
+        let dir =
+            std::env::temp_dir().join(format!("webgl-wrapper-codegen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let vert_path = dir.join("test.vert");
+        let frag_path = dir.join("test.frag");
+        fs::write(&vert_path, "in vec2 pos;\nuniform float scale;\nuniform vec2 offset;\n")
+            .unwrap();
+
+        fs::write(&frag_path, "uniform float scale;\nuniform sampler2D tex;\n").unwrap();
+        let bindings = generate_bindings("Test", &vert_path, &frag_path).unwrap();
+        assert!(
+            bindings.contains("pub struct TestVertex {\n    pub pos: ::cgmath::Vector2<f32>,\n}")
+        );
+        assert_eq!(bindings.matches("pub scale: f32,").count(), 1);
+
+        fs::write(&frag_path, "uniform int scale;\n").unwrap();
+        let err = generate_bindings("Test", &vert_path, &frag_path).unwrap_err();
+        assert_eq!(err, "Uniform scale has different types in each shader");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
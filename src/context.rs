@@ -1,3 +1,4 @@
+use log::*;
 use std::cell::RefCell;
 use std::rc::Rc;
 use wasm_bindgen::JsCast;
@@ -9,6 +10,7 @@ use crate::program::*;
 use crate::rect::*;
 use crate::surface::*;
 use crate::texture::*;
+use crate::uniform_buffer::*;
 
 pub(crate) type WebGl2 = WebGl2RenderingContext;
 
@@ -17,29 +19,75 @@ pub(crate) type WebGl2 = WebGl2RenderingContext;
 pub struct GlContext {
     pub(crate) inner: WebGl2RenderingContext,
     pub(crate) cache: Rc<RefCell<GlContextCache>>,
-    // A VAO/VBO that is currently used for all instanced rendering
-    // TODO: this isn't suitable for all cases of instanced rendering; some apps will want to
-    // use static data for the instances rather than recreating them each frame.
-    pub(crate) instanced_vao: WebGlVertexArrayObject,
+    // The buffer that `Mesh::draw_instanced` uploads instances to. Instances that don't change
+    // every frame can use an `InstanceBuffer` instead.
     pub(crate) instanced_vbo: WebGlBuffer,
+    // Whether KHR_parallel_shader_compile is supported
+    pub(crate) parallel_shader_compile: bool,
 }
 
 pub(crate) struct GlContextCache {
     pub draw_mode: Option<DrawMode>,
     pub bound_program: Option<ProgramId>,
+    // The program whose `GlUniforms` are being created, which the uniforms record
+    pub constructing_program: Option<ProgramId>,
     pub bound_framebuffer: Option<FramebufferId>,
     pub bound_read_framebuffer: Option<FramebufferId>,
-    pub bound_textures: [Option<(u32, TextureId)>; 32],
+    pub bound_textures: Vec<Option<(u32, TextureId)>>,
+    pub bound_uniform_buffers: Vec<Option<UniformBufferId>>,
+    // Which texture units have been assigned to a sampler in the current draw
+    pub claimed_texture_units: Vec<bool>,
 }
 
 impl GlContextCache {
-    fn new() -> Self {
+    fn new(max_texture_units: u32) -> Self {
         Self {
             draw_mode: None,
             bound_program: None,
+            constructing_program: None,
             bound_framebuffer: None,
             bound_read_framebuffer: None,
-            bound_textures: [None; 32],
+            bound_textures: vec![None; max_texture_units as usize],
+            bound_uniform_buffers: vec![],
+            claimed_texture_units: vec![false; max_texture_units as usize],
+        }
+    }
+
+    /// Starts a new draw, making all texture units available to `claim_texture_unit` again.
+    pub fn reset_texture_units(&mut self) {
+        for claimed in &mut self.claimed_texture_units {
+            *claimed = false;
+        }
+    }
+
+    /// Picks a texture unit for the given texture in the current draw. A unit that already has
+    /// the texture bound is preferred, followed by units that have nothing bound, so that as
+    /// few textures as possible have to be rebound.
+    pub fn claim_texture_unit(&mut self, texture: (u32, TextureId)) -> u32 {
+        // If the texture is already bound, it can be shared even by two samplers in this draw
+        let unit = self
+            .bound_textures
+            .iter()
+            .position(|&bound| bound == Some(texture))
+            .or_else(|| {
+                (0..self.bound_textures.len()).find(|&unit| {
+                    !self.claimed_texture_units[unit] && self.bound_textures[unit].is_none()
+                })
+            })
+            .or_else(|| self.claimed_texture_units.iter().position(|&claimed| !claimed));
+
+        match unit {
+            Some(unit) => {
+                self.claimed_texture_units[unit] = true;
+                unit as u32
+            }
+            None => {
+                error!(
+                    "Too many textures in one draw (MAX_COMBINED_TEXTURE_IMAGE_UNITS is {})",
+                    self.bound_textures.len()
+                );
+                panic!();
+            }
         }
     }
 }
@@ -48,6 +96,7 @@ impl GlContextCache {
 pub(crate) enum GlFlag {
     DepthTest,
     CullFace,
+    RasterizerDiscard,
 }
 
 impl GlFlag {
@@ -55,6 +104,7 @@ impl GlFlag {
         match self {
             GlFlag::DepthTest => WebGl2::DEPTH_TEST,
             GlFlag::CullFace => WebGl2::CULL_FACE,
+            GlFlag::RasterizerDiscard => WebGl2::RASTERIZER_DISCARD,
         }
     }
 }
@@ -80,17 +130,23 @@ impl GlContext {
         context.blend_func(WebGl2::ONE, WebGl2::ONE_MINUS_SRC_ALPHA);
         context.pixel_storei(WebGl2::UNPACK_ALIGNMENT, 1);
 
-        let instanced_vao = context.create_vertex_array().unwrap();
-        context.bind_vertex_array(Some(&instanced_vao));
+        let parallel_shader_compile =
+            matches!(context.get_extension("KHR_parallel_shader_compile"), Ok(Some(_)));
+
+        let max_texture_units = context
+            .get_parameter(WebGl2::MAX_COMBINED_TEXTURE_IMAGE_UNITS)
+            .unwrap()
+            .as_f64()
+            .unwrap() as u32;
+
         let instanced_vbo = context.create_buffer().unwrap();
-        context.bind_buffer(WebGl2::ARRAY_BUFFER, Some(&instanced_vbo));
 
         Ok((
             GlContext {
                 inner: context,
-                cache: Rc::new(RefCell::new(GlContextCache::new())),
-                instanced_vao,
+                cache: Rc::new(RefCell::new(GlContextCache::new(max_texture_units))),
                 instanced_vbo,
+                parallel_shader_compile,
             },
             ScreenSurface::new(canvas),
         ))
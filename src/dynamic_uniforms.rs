@@ -0,0 +1,240 @@
+use cgmath::*;
+use log::*;
+use serde::*;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use web_sys::*;
+
+use crate::context::*;
+use crate::program::*;
+use crate::texture::*;
+use crate::uniforms::*;
+
+/// The value of a uniform in `DynamicUniforms`.
+///
+/// Matrices are given as arrays of columns. A `Texture` refers by name to a texture that was
+/// added with `DynamicUniforms::add_texture`, since textures can't be deserialized.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DynamicValue {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Int(i32),
+    IVec2([i32; 2]),
+    IVec3([i32; 3]),
+    IVec4([i32; 4]),
+    UInt(u32),
+    UVec2([u32; 2]),
+    UVec3([u32; 3]),
+    UVec4([u32; 4]),
+    Bool(bool),
+    Mat2([[f32; 2]; 2]),
+    Mat3([[f32; 3]; 3]),
+    Mat4([[f32; 4]; 4]),
+    FloatArray(Vec<f32>),
+    Vec2Array(Vec<[f32; 2]>),
+    Vec3Array(Vec<[f32; 3]>),
+    Vec4Array(Vec<[f32; 4]>),
+    Texture(String),
+}
+
+impl DynamicValue {
+    /// Returns the GL type of the uniform that this value can be assigned to, and the number
+    /// of array elements it contains.
+    fn gl_type(&self) -> (u32, usize) {
+        match self {
+            DynamicValue::Float(_) => (WebGl2::FLOAT, 1),
+            DynamicValue::Vec2(_) => (WebGl2::FLOAT_VEC2, 1),
+            DynamicValue::Vec3(_) => (WebGl2::FLOAT_VEC3, 1),
+            DynamicValue::Vec4(_) => (WebGl2::FLOAT_VEC4, 1),
+            DynamicValue::Int(_) => (WebGl2::INT, 1),
+            DynamicValue::IVec2(_) => (WebGl2::INT_VEC2, 1),
+            DynamicValue::IVec3(_) => (WebGl2::INT_VEC3, 1),
+            DynamicValue::IVec4(_) => (WebGl2::INT_VEC4, 1),
+            DynamicValue::UInt(_) => (WebGl2::UNSIGNED_INT, 1),
+            DynamicValue::UVec2(_) => (WebGl2::UNSIGNED_INT_VEC2, 1),
+            DynamicValue::UVec3(_) => (WebGl2::UNSIGNED_INT_VEC3, 1),
+            DynamicValue::UVec4(_) => (WebGl2::UNSIGNED_INT_VEC4, 1),
+            DynamicValue::Bool(_) => (WebGl2::BOOL, 1),
+            DynamicValue::Mat2(_) => (WebGl2::FLOAT_MAT2, 1),
+            DynamicValue::Mat3(_) => (WebGl2::FLOAT_MAT3, 1),
+            DynamicValue::Mat4(_) => (WebGl2::FLOAT_MAT4, 1),
+            DynamicValue::FloatArray(values) => (WebGl2::FLOAT, values.len()),
+            DynamicValue::Vec2Array(values) => (WebGl2::FLOAT_VEC2, values.len()),
+            DynamicValue::Vec3Array(values) => (WebGl2::FLOAT_VEC3, values.len()),
+            DynamicValue::Vec4Array(values) => (WebGl2::FLOAT_VEC4, values.len()),
+            DynamicValue::Texture(_) => (WebGl2::SAMPLER_2D, 1),
+        }
+    }
+
+    /// Uploads the value, except for textures, which need to be bound to a texture unit first.
+    fn set_uniform(&self, context: &GlContext, loc: &WebGlUniformLocation) {
+        match self {
+            DynamicValue::Float(x) => x.set_uniform(context, loc),
+            DynamicValue::Vec2(x) => x.set_uniform(context, loc),
+            DynamicValue::Vec3(x) => x.set_uniform(context, loc),
+            DynamicValue::Vec4(x) => x.set_uniform(context, loc),
+            DynamicValue::Int(x) => x.set_uniform(context, loc),
+            DynamicValue::IVec2(x) => x.set_uniform(context, loc),
+            DynamicValue::IVec3(x) => x.set_uniform(context, loc),
+            DynamicValue::IVec4(x) => x.set_uniform(context, loc),
+            DynamicValue::UInt(x) => x.set_uniform(context, loc),
+            DynamicValue::UVec2(x) => x.set_uniform(context, loc),
+            DynamicValue::UVec3(x) => x.set_uniform(context, loc),
+            DynamicValue::UVec4(x) => x.set_uniform(context, loc),
+            DynamicValue::Bool(x) => x.set_uniform(context, loc),
+            DynamicValue::Mat2(x) => Matrix2::from(*x).set_uniform(context, loc),
+            DynamicValue::Mat3(x) => Matrix3::from(*x).set_uniform(context, loc),
+            DynamicValue::Mat4(x) => Matrix4::from(*x).set_uniform(context, loc),
+            DynamicValue::FloatArray(x) => x[..].set_uniform(context, loc),
+            DynamicValue::Vec2Array(x) => x[..].set_uniform(context, loc),
+            DynamicValue::Vec3Array(x) => x[..].set_uniform(context, loc),
+            DynamicValue::Vec4Array(x) => x[..].set_uniform(context, loc),
+            DynamicValue::Texture(_) => unreachable!(),
+        }
+    }
+}
+
+/// Uniforms that are looked up by name when drawing, for materials that are defined by data
+/// rather than by a Rust struct.
+///
+/// Values are matched against the uniforms that the program reports as active. A value whose
+/// uniform doesn't exist, or has a different type, is skipped and a warning is logged the
+/// first time that happens for each program.
+///
+/// This serializes as a map from uniform names to values. Textures are added separately, and
+/// referred to with `DynamicValue::Texture`.
+///
+/// Example usage:
+/// ```
+/// let mut uniforms: DynamicUniforms = serde_json::from_str(r#"{
+///     "tint": { "Vec4": [1.0, 0.5, 0.5, 1.0] },
+///     "albedo": { "Texture": "brick" }
+/// }"#)?;
+/// uniforms.add_texture("brick", &brick_texture);
+/// uniforms.set("view_proj", DynamicValue::Mat4(view_proj.into()));
+/// mesh.draw(&surface, &uniforms);
+/// ```
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DynamicUniforms<'a> {
+    values: HashMap<String, DynamicValue>,
+    #[serde(skip)]
+    textures: HashMap<String, &'a Texture2d>,
+}
+
+impl<'a> DynamicUniforms<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of the uniform with the given name.
+    pub fn set(&mut self, name: &str, value: DynamicValue) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DynamicValue> {
+        self.values.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<DynamicValue> {
+        self.values.remove(name)
+    }
+
+    /// Adds a texture that `DynamicValue::Texture(name)` values can refer to.
+    pub fn add_texture(&mut self, name: &str, texture: &'a Texture2d) {
+        self.textures.insert(name.to_string(), texture);
+    }
+}
+
+impl<'a> Uniforms for DynamicUniforms<'a> {
+    type GlUniforms = DynamicGlUniforms;
+
+    fn update(&self, program: &BoundProgram<'_, DynamicGlUniforms>) {
+        let gl_uniforms = program.uniforms();
+        for (name, value) in &self.values {
+            let uniform = match gl_uniforms.uniforms.get(name) {
+                Some(uniform) => uniform,
+                None => {
+                    gl_uniforms.warn_once(name, || format!("Uniform {} not found", name));
+                    continue;
+                }
+            };
+
+            let (ty, len) = value.gl_type();
+            if ty != uniform.ty || len > uniform.size {
+                gl_uniforms.warn_once(name, || {
+                    format!(
+                        "Uniform {} has type {:#x}[{}], but was given {:?}",
+                        name, uniform.ty, uniform.size, value
+                    )
+                });
+                continue;
+            }
+
+            if let DynamicValue::Texture(texture_name) = value {
+                match self.textures.get(texture_name) {
+                    Some(texture) => {
+                        let texture_unit = texture.bind_for_draw();
+                        program.context().inner.uniform1i(Some(&uniform.loc), texture_unit as i32);
+                    }
+                    None => gl_uniforms.warn_once(name, || {
+                        format!("Texture {} for uniform {} not found", texture_name, name)
+                    }),
+                }
+            } else {
+                value.set_uniform(program.context(), &uniform.loc);
+            }
+        }
+    }
+}
+
+struct DynamicUniform {
+    loc: WebGlUniformLocation,
+    ty: u32,
+    size: usize,
+}
+
+/// The `GlUniforms` for `DynamicUniforms`, which holds every active uniform in the program.
+pub struct DynamicGlUniforms {
+    uniforms: HashMap<String, DynamicUniform>,
+    /// The uniforms that a warning has already been logged for.
+    warned: RefCell<HashSet<String>>,
+}
+
+impl DynamicGlUniforms {
+    /// Returns the names of the program's active uniforms. Uniforms in uniform blocks aren't
+    /// included.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.uniforms.keys().map(|name| name.as_str())
+    }
+
+    fn warn_once(&self, name: &str, message: impl FnOnce() -> String) {
+        if self.warned.borrow_mut().insert(name.to_string()) {
+            warn!("{}", message());
+        }
+    }
+}
+
+impl GlUniforms for DynamicGlUniforms {
+    fn new(context: &GlContext, program: &WebGlProgram) -> Self {
+        let num_uniforms =
+            context.inner.get_program_parameter(program, WebGl2::ACTIVE_UNIFORMS).as_f64().unwrap()
+                as u32;
+        let mut uniforms = HashMap::new();
+        for i in 0..num_uniforms {
+            if let Some(info) = context.inner.get_active_uniform(program, i) {
+                // Uniforms in blocks don't have a location
+                if let Some(loc) = context.inner.get_uniform_location(program, &info.name()) {
+                    // Arrays are reported with the name of their first element
+                    let name = info.name().trim_end_matches("[0]").to_string();
+                    let uniform =
+                        DynamicUniform { loc, ty: info.type_(), size: info.size() as usize };
+                    uniforms.insert(name, uniform);
+                }
+            }
+        }
+        Self { uniforms, warned: RefCell::new(HashSet::new()) }
+    }
+}
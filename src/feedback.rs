@@ -0,0 +1,163 @@
+use js_sys::WebAssembly::Memory;
+use js_sys::*;
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::mem;
+use wasm_bindgen::{memory, JsCast};
+use web_sys::*;
+
+use crate::context::*;
+use crate::mesh::*;
+use crate::program::*;
+use crate::surface::*;
+use crate::uniforms::*;
+use crate::vertex::*;
+
+/// A buffer that transform feedback output can be written to.
+///
+/// This is implemented by `FeedbackBuffer`; it allows buffers with different vertex types to be
+/// passed to `GlProgram::capture` together.
+pub trait FeedbackTarget {
+    /// The number of vertices that the buffer has room for.
+    fn capacity(&self) -> usize;
+
+    #[doc(hidden)]
+    fn buffer(&self) -> &WebGlBuffer;
+
+    #[doc(hidden)]
+    fn set_len(&self, len: usize);
+}
+
+/// A buffer of vertices that can be written by transform feedback (see `GlProgram::capture`).
+///
+/// The contents can be drawn directly, or used as the input to another capture; alternating
+/// between two `FeedbackBuffer`s allows state such as particle positions to be updated every
+/// frame without copying it back to the CPU.
+///
+/// Like instance data, `T` must use `#[repr(C)]`, and its fields must be in the same order as
+/// its `VertexData` impl specifies. `P` must be `Points`, `Lines`, or `Triangles`, since those
+/// are the only primitives that transform feedback can capture.
+pub struct FeedbackBuffer<T: VertexData, P: FeedbackPrimitive> {
+    vao: WebGlVertexArrayObject,
+    buffer: WebGlBuffer,
+    capacity: usize,
+    len: Cell<usize>,
+    context: GlContext,
+    phantom: PhantomData<(T, P)>,
+}
+
+impl<T: VertexData, P: FeedbackPrimitive> Drop for FeedbackBuffer<T, P> {
+    fn drop(&mut self) {
+        self.context.inner.delete_vertex_array(Some(&self.vao));
+        self.context.inner.delete_buffer(Some(&self.buffer));
+    }
+}
+
+impl<T: VertexData, P: FeedbackPrimitive> FeedbackBuffer<T, P> {
+    /// Creates an empty `FeedbackBuffer` with room for `capacity` vertices.
+    pub fn new(context: &GlContext, capacity: usize, usage: MeshUsage) -> Self {
+        let res = Self::create(context, capacity);
+        context.inner.buffer_data_with_i32(
+            WebGl2::ARRAY_BUFFER,
+            (capacity * T::stride() as usize) as i32,
+            usage.as_gl(),
+        );
+        res
+    }
+
+    /// Creates a `FeedbackBuffer` containing the given vertices, with room for exactly that
+    /// many vertices.
+    pub fn from_data(context: &GlContext, data: &[T], usage: MeshUsage) -> Self {
+        let res = Self::create(context, data.len());
+
+        let memory_buffer = memory().dyn_into::<Memory>().unwrap().buffer();
+        debug_assert_eq!(mem::size_of::<T>(), T::stride() as usize);
+        let data_loc = data.as_ptr() as u32;
+        let data_array = Uint8Array::new(&memory_buffer)
+            .subarray(data_loc, data_loc + data.len() as u32 * T::stride() as u32);
+        context.inner.buffer_data_with_array_buffer_view(
+            WebGl2::ARRAY_BUFFER,
+            &data_array,
+            usage.as_gl(),
+        );
+
+        res.len.set(data.len());
+        res
+    }
+
+    fn create(context: &GlContext, capacity: usize) -> Self {
+        let vao = context.inner.create_vertex_array().unwrap();
+        context.inner.bind_vertex_array(Some(&vao));
+        let buffer = context.inner.create_buffer().unwrap();
+        context.inner.bind_buffer(WebGl2::ARRAY_BUFFER, Some(&buffer));
+
+        FeedbackBuffer {
+            vao,
+            buffer,
+            capacity,
+            len: Cell::new(0),
+            context: context.clone(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the number of vertices that were written by the last capture, or by `from_data`.
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Binds the buffer as the vertex data for the given program.
+    pub(crate) fn bind<V: Vertex, U: GlUniforms>(&self, program: &GlProgram<V, U>) {
+        self.context.inner.bind_vertex_array(Some(&self.vao));
+        self.context.inner.bind_buffer(WebGl2::ARRAY_BUFFER, Some(&self.buffer));
+        setup_vertex_attribs::<T>(
+            &self.context,
+            &program.inner.state.borrow().objects.program,
+            false,
+            0,
+        );
+    }
+}
+
+impl<T: Vertex, P: FeedbackPrimitive> FeedbackBuffer<T, P> {
+    /// Draws the buffer's contents as non-indexed primitives.
+    pub fn draw<U: GlUniforms>(
+        &self,
+        surface: &(impl Surface + ?Sized),
+        program: &GlProgram<T, U>,
+        uniforms: &impl Uniforms<GlUniforms = U>,
+        draw_mode: DrawMode,
+    ) {
+        if self.is_empty() {
+            return;
+        }
+
+        self.bind(program);
+        let bound_program = program.bind(&self.context);
+        uniforms.update(&bound_program);
+        surface.bind(&self.context);
+        draw_mode.bind(&self.context);
+
+        self.context.inner.draw_arrays(P::AS_GL, 0, self.len() as i32);
+    }
+}
+
+impl<T: VertexData, P: FeedbackPrimitive> FeedbackTarget for FeedbackBuffer<T, P> {
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[doc(hidden)]
+    fn buffer(&self) -> &WebGlBuffer {
+        &self.buffer
+    }
+
+    #[doc(hidden)]
+    fn set_len(&self, len: usize) {
+        self.len.set(len);
+    }
+}
@@ -0,0 +1,376 @@
+use std::fmt;
+
+use crate::program::*;
+
+/// An error found by `validate_shader`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GlslError {
+    /// The name of the file that contains the shader, as passed to `validate_shader`.
+    pub file_name: String,
+    /// The line the error is on, starting from 1. This is 0 if the error isn't associated with
+    /// a particular location.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for GlslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file_name, self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.file_name, self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for GlslError {}
+
+/// Parses and validates a GLSL ES 3.00 shader without a browser or GPU, using naga. This
+/// requires the `naga` feature.
+///
+/// Naga only accepts Vulkan-style desktop GLSL, so the source is first converted to that: the
+/// `#version` line is replaced, `in` and `out` variables are given locations, uniforms outside
+/// blocks are wrapped in blocks, and each sampler is split into a texture and a sampler that are
+/// combined wherever it's used. Each change is made without adding lines, so the line numbers in
+/// errors match the original source. Samplers can't be passed to functions, and sampler arrays
+/// aren't supported because naga can't parse arrays of textures.
+pub fn validate_shader(
+    file_name: &str,
+    source: &str,
+    shader_type: ShaderType,
+) -> Result<(), Vec<GlslError>> {
+    use naga::front::glsl::{Frontend, Options};
+    use naga::valid::{Capabilities, ValidationFlags, Validator};
+
+    let stage = match shader_type {
+        ShaderType::Vertex => naga::ShaderStage::Vertex,
+        ShaderType::Fragment => naga::ShaderStage::Fragment,
+    };
+    let source = to_vulkan_glsl(source).map_err(|(line, message)| {
+        vec![GlslError { file_name: file_name.to_string(), line, message }]
+    })?;
+
+    let error = |span: naga::Span, message: String| GlslError {
+        file_name: file_name.to_string(),
+        line: if span.is_defined() { span.location(&source).line_number as usize } else { 0 },
+        message,
+    };
+
+    let module = Frontend::default().parse(&Options::from(stage), &source).map_err(|err| {
+        err.errors.into_iter().map(|err| error(err.meta, err.kind.to_string())).collect::<Vec<_>>()
+    })?;
+
+    Validator::new(ValidationFlags::all(), Capabilities::empty()).validate(&module).map_err(
+        |err| {
+            let span = err.spans().next().map(|(span, _)| *span).unwrap_or_default();
+            vec![error(span, err.as_inner().to_string())]
+        },
+    )?;
+    Ok(())
+}
+
+/// Validates the vertex and fragment shaders of a program, given as `(file_name, source)`
+/// pairs. Errors from both shaders are returned.
+///
+/// Example usage, in a test function:
+/// ```
+/// validate_program(
+///     ("sprite.vert", include_str!("shaders/sprite.vert")),
+///     ("sprite.frag", include_str!("shaders/sprite.frag")),
+/// )
+/// .unwrap_or_else(|errors| {
+///     panic!("{}", errors.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("\n"))
+/// });
+/// ```
+pub fn validate_program(
+    vert_shader: (&str, &str),
+    frag_shader: (&str, &str),
+) -> Result<(), Vec<GlslError>> {
+    let mut errors = vec![];
+    if let Err(err) = validate_shader(vert_shader.0, vert_shader.1, ShaderType::Vertex) {
+        errors.extend(err);
+    }
+    if let Err(err) = validate_shader(frag_shader.0, frag_shader.1, ShaderType::Fragment) {
+        errors.extend(err);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Converts GLSL ES 3.00 to GLSL 4.50 with explicit locations and bindings, one line at a time.
+/// Only declarations at global scope are changed, and each must be on a single line.
+///
+/// Returns the line number and a message if the source uses something that can't be converted.
+fn to_vulkan_glsl(source: &str) -> Result<String, (usize, String)> {
+    let mut res = String::with_capacity(source.len() * 2);
+    let mut depth = 0i32;
+    let mut next_in_location = 0;
+    let mut next_out_location = 0;
+    let mut next_binding = 0;
+    // The name of each sampler that was split, and the expression that combines it again
+    let mut samplers: Vec<(String, String)> = vec![];
+
+    for (line_index, line) in source.lines().enumerate() {
+        let (code, comment) = match line.find("//") {
+            Some(i) => line.split_at(i),
+            None => (line, ""),
+        };
+        let trimmed = code.trim();
+        let tokens: Vec<&str> = trimmed.split_whitespace().collect();
+
+        if trimmed.starts_with("#version") {
+            res.push_str("#version 450");
+        } else if depth == 0 && tokens.first() == Some(&"uniform") {
+            let binding = next_binding;
+            next_binding += 1;
+            let is_block = !trimmed.ends_with(';');
+            let sampler = tokens
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find_map(|(i, token)| split_sampler_type(token).map(|types| (i, types)));
+            if is_block {
+                res.push_str(&format!("layout(binding = {}) {}", binding, trimmed));
+            } else if let Some((i, (texture_type, sampler_type))) = sampler {
+                let name = tokens.get(i + 1).map_or("", |name| name.trim_end_matches(';'));
+                if let Some(j) = name.find('[') {
+                    let message = format!("sampler array {} isn't supported", &name[..j]);
+                    return Err((line_index + 1, message));
+                }
+                let sampler_binding = next_binding;
+                next_binding += 1;
+                res.push_str(&format!(
+                    "layout(binding = {}) {} layout(binding = {}) uniform {} _{}_sampler;",
+                    binding,
+                    trimmed.replacen(tokens[i], &texture_type, 1),
+                    sampler_binding,
+                    sampler_type,
+                    name
+                ));
+                samplers.push((
+                    name.to_string(),
+                    format!("{}({}, _{}_sampler)", tokens[i], name, name),
+                ));
+            } else {
+                res.push_str(&format!(
+                    "layout(binding = {}) uniform _Uniform{} {{ {} }};",
+                    binding,
+                    binding,
+                    trimmed["uniform".len()..].trim()
+                ));
+            }
+        } else if depth == 0 && tokens.first().is_some_and(|token| token.starts_with("layout")) {
+            // Existing layouts are left alone, apart from adding a binding to uniform blocks
+            if tokens.contains(&"uniform") && !trimmed.contains("binding") {
+                let binding = next_binding;
+                next_binding += 1;
+                res.push_str(&trimmed.replacen(')', &format!(", binding = {})", binding), 1));
+            } else {
+                res.push_str(trimmed);
+            }
+        } else if depth == 0 && is_interface_declaration(&tokens) {
+            let location = if tokens.contains(&"in") {
+                next_in_location += 1;
+                next_in_location - 1
+            } else {
+                next_out_location += 1;
+                next_out_location - 1
+            };
+            res.push_str(&format!("layout(location = {}) {}", location, trimmed));
+        } else {
+            res.push_str(&combine_samplers(code, &samplers));
+        }
+
+        res.push_str(comment);
+        res.push('\n');
+        depth += code.matches('{').count() as i32 - code.matches('}').count() as i32;
+    }
+    Ok(res)
+}
+
+/// Returns whether the tokens are an `in` or `out` variable declaration without a layout.
+fn is_interface_declaration(tokens: &[&str]) -> bool {
+    let qualifiers = ["flat", "smooth", "centroid"];
+    tokens
+        .iter()
+        .find(|token| !qualifiers.contains(token))
+        .is_some_and(|&token| token == "in" || token == "out")
+}
+
+/// Splits a combined sampler type such as `sampler2D` into the texture and sampler types that
+/// naga expects in its place, such as `texture2D` and `sampler`.
+fn split_sampler_type(token: &str) -> Option<(String, &'static str)> {
+    let prefix = &token[..token.len() - token.trim_start_matches(['i', 'u']).len()];
+    let dim = token[prefix.len()..].strip_prefix("sampler").filter(|dim| !dim.is_empty())?;
+    Some(match dim.strip_suffix("Shadow") {
+        Some(dim) => (format!("{}texture{}", prefix, dim), "samplerShadow"),
+        None => (format!("{}texture{}", prefix, dim), "sampler"),
+    })
+}
+
+/// Replaces each use of a sampler that was split by `to_vulkan_glsl` with the expression that
+/// combines it again.
+fn combine_samplers(code: &str, samplers: &[(String, String)]) -> String {
+    let is_ident_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut res = String::with_capacity(code.len());
+    let mut rest = code;
+    while let Some(start) = rest.find(is_ident_char) {
+        let len = rest[start..].find(|c| !is_ident_char(c)).unwrap_or(rest.len() - start);
+        let ident = &rest[start..start + len];
+        res.push_str(&rest[..start]);
+        // Struct fields can have the same name as a sampler
+        match samplers.iter().find(|(name, _)| name == ident) {
+            Some((_, combined)) if !res.ends_with('.') => res.push_str(combined),
+            _ => res.push_str(ident),
+        }
+        rest = &rest[start + len..];
+    }
+    res.push_str(rest);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(line: &str) -> Vec<&str> {
+        line.split_whitespace().collect()
+    }
+
+    #[test]
+    fn interface_declarations() {
+        assert!(is_interface_declaration(&tokens("in vec2 pos;")));
+        assert!(is_interface_declaration(&tokens("out vec4 color;")));
+        assert!(is_interface_declaration(&tokens("flat in int id;")));
+        assert!(is_interface_declaration(&tokens("smooth centroid out vec2 uv;")));
+        assert!(!is_interface_declaration(&tokens("uniform float time;")));
+        assert!(!is_interface_declaration(&tokens("vec2 in_pos;")));
+        assert!(!is_interface_declaration(&tokens("flat int id;")));
+        assert!(!is_interface_declaration(&[]));
+    }
+
+    #[test]
+    fn vulkan_glsl_declarations() {
+        let source = "\
+#version 300 es
+precision mediump float;
+in vec2 pos; // the position
+flat in int id;
+out vec4 color;
+uniform float time;
+uniform sampler2D tex;
+uniform Camera {
+    mat4 view_proj;
+};
+layout(std140) uniform Lights {
+    vec4 color;
+};
+void main() {
+    vec2 x = pos;
+}
+";
+        let expected = "\
+#version 450
+precision mediump float;
+layout(location = 0) in vec2 pos;// the position
+layout(location = 1) flat in int id;
+layout(location = 0) out vec4 color;
+layout(binding = 0) uniform _Uniform0 { float time; };
+layout(binding = 1) uniform texture2D tex; layout(binding = 2) uniform sampler _tex_sampler;
+layout(binding = 3) uniform Camera {
+    mat4 view_proj;
+};
+layout(std140, binding = 4) uniform Lights {
+    vec4 color;
+};
+void main() {
+    vec2 x = pos;
+}
+";
+        assert_eq!(to_vulkan_glsl(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn samplers_are_split() {
+        assert_eq!(split_sampler_type("sampler2D"), Some(("texture2D".to_string(), "sampler")));
+        assert_eq!(split_sampler_type("usampler3D"), Some(("utexture3D".to_string(), "sampler")));
+        assert_eq!(
+            split_sampler_type("sampler2DArrayShadow"),
+            Some(("texture2DArray".to_string(), "samplerShadow"))
+        );
+        assert_eq!(split_sampler_type("sampler"), None);
+        assert_eq!(split_sampler_type("vec4"), None);
+
+        let samplers = [("tex".to_string(), "sampler2D(tex, _tex_sampler)".to_string())];
+        assert_eq!(
+            combine_samplers("color = texture(tex, uv) + light.tex + textures;", &samplers),
+            "color = texture(sampler2D(tex, _tex_sampler), uv) + light.tex + textures;"
+        );
+    }
+
+    #[test]
+    fn vulkan_glsl_keeps_line_numbers() {
+        let source = "#version 300 es\nuniform vec2 a;\n\nin vec3 b;\nvoid main() {}\n";
+        assert_eq!(to_vulkan_glsl(source).unwrap().lines().count(), source.lines().count());
+    }
+
+    #[test]
+    fn valid_program() {
+        let vert = "\
+#version 300 es
+in vec2 pos;
+out vec2 uv;
+uniform mat4 transform;
+void main() {
+    uv = pos;
+    gl_Position = transform * vec4(pos, 0.0, 1.0);
+}
+";
+        let frag = "\
+#version 300 es
+precision mediump float;
+in vec2 uv;
+out vec4 color;
+uniform sampler2D tex;
+uniform highp sampler2DShadow shadow_map;
+void main() {
+    color = texture(tex, uv) * texture(shadow_map, vec3(uv, 0.5));
+}
+";
+        assert_eq!(validate_program(("test.vert", vert), ("test.frag", frag)), Ok(()));
+    }
+
+    #[test]
+    fn errors_have_the_original_line() {
+        let frag = "\
+#version 300 es
+precision mediump float;
+out vec4 color;
+void main() {
+    color = undefined_variable;
+}
+";
+        let errors = validate_shader("test.frag", frag, ShaderType::Fragment).unwrap_err();
+        assert!(!errors.is_empty());
+        assert_eq!(errors[0].file_name, "test.frag");
+        assert_eq!(errors[0].line, 5);
+        assert!(errors[0].to_string().starts_with("test.frag:5: "));
+    }
+
+    #[test]
+    fn sampler_arrays_are_rejected() {
+        let frag = "\
+#version 300 es
+precision mediump float;
+uniform sampler2D layers[4];
+void main() {}
+";
+        let errors = validate_shader("test.frag", frag, ShaderType::Fragment).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+        assert_eq!(errors[0].message, "sampler array layers isn't supported");
+    }
+}
@@ -0,0 +1,83 @@
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::buffer::*;
+use crate::context::*;
+use crate::mesh::*;
+use crate::vertex::*;
+
+/// Per-instance data that stays on the GPU between draws, for use with
+/// `Mesh::draw_instanced_buffer`.
+///
+/// `Mesh::draw_instanced` uploads its instances on every call, which is wasteful if most of them
+/// don't change. An `InstanceBuffer` is uploaded once, and can then be partially updated with
+/// `update`.
+///
+/// Like the instances passed to `Mesh::draw_instanced`, `I` must use `#[repr(C)]`, and its
+/// fields must be in the same order as its `VertexData` impl specifies.
+///
+/// Example usage:
+/// ```
+/// let mut trees = InstanceBuffer::from_data(&context, &tree_instances, MeshUsage::StaticDraw);
+/// // Every frame:
+/// tree_mesh.draw_instanced_buffer(&surface, &uniforms, &trees);
+/// // When a tree is cut down:
+/// trees.update(index, &[TreeInstance { scale: 0.0, ..tree_instances[index] }]);
+/// ```
+pub struct InstanceBuffer<I: VertexData> {
+    pub(crate) buffer: GlBuffer,
+    phantom: PhantomData<I>,
+}
+
+impl<I: VertexData> InstanceBuffer<I> {
+    /// Creates an empty `InstanceBuffer`.
+    pub fn new(context: &GlContext, usage: MeshUsage) -> Self {
+        // The instances are uploaded as they are in memory, so the attributes have to cover every
+        // byte of `I`.
+        assert_eq!(mem::size_of::<I>(), I::stride() as usize);
+        InstanceBuffer {
+            buffer: GlBuffer::new(context, WebGl2::ARRAY_BUFFER, usage),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Creates an `InstanceBuffer` containing the given instances.
+    pub fn from_data(context: &GlContext, instances: &[I], usage: MeshUsage) -> Self {
+        let mut res = Self::new(context, usage);
+        res.set_data(instances);
+        res
+    }
+
+    /// Returns the number of instances in the buffer.
+    pub fn len(&self) -> usize {
+        self.buffer.len() / I::stride() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of instances that the buffer has room for without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity() / I::stride() as usize
+    }
+
+    /// Replaces all instances in the buffer.
+    pub fn set_data(&mut self, instances: &[I]) {
+        // SAFETY: `new` checked that the attributes fill `I`, so it has no padding.
+        self.buffer.set_data(unsafe { slice_as_bytes(instances) });
+    }
+
+    /// Replaces the instances starting at `start`. `start` can be at most `len()`; instances past
+    /// the end are appended.
+    pub fn update(&mut self, start: usize, instances: &[I]) {
+        // SAFETY: `new` checked that the attributes fill `I`, so it has no padding.
+        let bytes = unsafe { slice_as_bytes(instances) };
+        self.buffer.write(start * I::stride() as usize, bytes);
+    }
+
+    /// Removes all instances after the first `len`.
+    pub fn truncate(&mut self, len: usize) {
+        self.buffer.truncate(len * I::stride() as usize);
+    }
+}
@@ -2,20 +2,59 @@
 
 #![deny(bare_trait_objects)]
 
+// Lets the code generated by the derive macros refer to this crate by name from inside it
+extern crate self as webgl_wrapper;
+
+mod buffer;
+#[cfg(feature = "codegen")]
+mod codegen;
 mod context;
+mod dynamic_uniforms;
+mod feedback;
 mod framebuffer;
+#[cfg(feature = "naga")]
+mod glsl_validation;
+mod instance_buffer;
 mod mesh;
+#[cfg(feature = "bytemuck")]
+mod pod;
 mod program;
+mod program_cache;
 mod rect;
+mod shader_source;
+mod std140;
+mod streaming_mesh;
 mod surface;
 mod texture;
+mod uniform_buffer;
 pub mod uniforms;
+mod vertex;
+mod vertex_stream;
+#[cfg(feature = "wgsl")]
+mod wgsl;
 
+#[cfg(feature = "codegen")]
+pub use crate::codegen::*;
 pub use crate::context::*;
+pub use crate::dynamic_uniforms::*;
+pub use crate::feedback::*;
 pub use crate::framebuffer::*;
+#[cfg(feature = "naga")]
+pub use crate::glsl_validation::*;
+pub use crate::instance_buffer::*;
 pub use crate::mesh::*;
 pub use crate::program::*;
+pub use crate::program_cache::*;
 pub use crate::rect::*;
+pub use crate::shader_source::*;
+pub use crate::std140::*;
+pub use crate::streaming_mesh::*;
 pub use crate::surface::*;
 pub use crate::texture::*;
+pub use crate::uniform_buffer::*;
+pub use crate::vertex::*;
+pub use crate::vertex_stream::*;
+#[cfg(feature = "wgsl")]
+pub use crate::wgsl::*;
 pub use uniforms::{GlUniforms, Uniforms};
+pub use webgl_wrapper_derive::Std140;
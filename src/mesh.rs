@@ -1,21 +1,38 @@
 use js_sys::WebAssembly::Memory;
 use js_sys::*;
+use log::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
 use wasm_bindgen::{memory, JsCast};
 use web_sys::*;
 
+use crate::buffer::*;
 use crate::context::*;
+use crate::instance_buffer::*;
 use crate::program::*;
 use crate::surface::*;
 use crate::uniforms::*;
+use crate::vertex::*;
+use crate::vertex_stream::*;
 
 /// An OpenGL primitive.
 #[doc(hidden)]
 pub trait Primitive {
     const AS_GL: u32;
+    /// True for strips, fans, and loops, where consecutive primitives share vertices.
+    const IS_STRIP: bool = false;
 }
 
-#[derive(Copy, Clone, Debug)]
+/// A primitive that transform feedback can capture: `Points`, `Lines`, or `Triangles`. WebGL 2
+/// doesn't allow strips, fans, or loops in `beginTransformFeedback`.
+#[doc(hidden)]
+pub trait FeedbackPrimitive: Primitive {}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum MeshUsage {
     StaticDraw,
     DynamicDraw,
@@ -23,7 +40,7 @@ pub enum MeshUsage {
 }
 
 impl MeshUsage {
-    fn as_gl(self) -> u32 {
+    pub(crate) fn as_gl(self) -> u32 {
         match self {
             MeshUsage::StaticDraw => WebGl2::STATIC_DRAW,
             MeshUsage::DynamicDraw => WebGl2::DYNAMIC_DRAW,
@@ -39,7 +56,7 @@ pub enum DrawMode {
 }
 
 impl DrawMode {
-    fn bind(self, context: &GlContext) {
+    pub(crate) fn bind(self, context: &GlContext) {
         let mut cache = context.cache.borrow_mut();
         if cache.draw_mode != Some(self) {
             cache.draw_mode = Some(self);
@@ -59,32 +76,140 @@ impl DrawMode {
 }
 
 /// An index into a mesh.
-pub type MeshIndex = u16;
+///
+/// Indices are stored as `u32`s while building a mesh, and converted to an `IndexFormat` that
+/// can hold them when the mesh is built.
+pub type MeshIndex = u32;
+
+/// Separates strips, fans, and loops in the builder's indices. This becomes the largest value
+/// of the mesh's `IndexFormat`, which WebGL 2 always treats as a primitive restart
+/// (`PRIMITIVE_RESTART_FIXED_INDEX` is always enabled).
+pub(crate) const RESTART_INDEX: MeshIndex = MeshIndex::MAX;
+
+/// The type that a mesh's indices are stored as on the GPU.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IndexFormat {
+    /// `u8` indices. Some platforms convert these to `u16`s on every draw, so this is never
+    /// chosen automatically; see `MeshBuilder::set_index_format`.
+    U8,
+    U16,
+    U32,
+}
+
+impl IndexFormat {
+    pub(crate) fn as_gl(self) -> u32 {
+        match self {
+            IndexFormat::U8 => WebGl2::UNSIGNED_BYTE,
+            IndexFormat::U16 => WebGl2::UNSIGNED_SHORT,
+            IndexFormat::U32 => WebGl2::UNSIGNED_INT,
+        }
+    }
+
+    pub fn size_in_bytes(self) -> i32 {
+        match self {
+            IndexFormat::U8 => 1,
+            IndexFormat::U16 => 2,
+            IndexFormat::U32 => 4,
+        }
+    }
+
+    /// The number of vertices that indices of this format can refer to. WebGL 2 always treats
+    /// the largest value as a primitive restart, so it can't be used as an index.
+    pub fn max_vertices(self) -> u32 {
+        match self {
+            IndexFormat::U8 => u8::MAX as u32,
+            IndexFormat::U16 => u16::MAX as u32,
+            IndexFormat::U32 => u32::MAX,
+        }
+    }
+
+    /// Returns `U16` if it can index the given number of vertices, and `U32` otherwise.
+    pub fn for_num_vertices(num_vertices: u32) -> Self {
+        if num_vertices <= IndexFormat::U16.max_vertices() {
+            IndexFormat::U16
+        } else {
+            IndexFormat::U32
+        }
+    }
+
+    /// Converts the indices to this format, as bytes.
+    pub(crate) fn convert(self, indices: &[MeshIndex]) -> Vec<u8> {
+        let mut res = Vec::with_capacity(indices.len() * self.size_in_bytes() as usize);
+        for &index in indices {
+            // The restart index for each format is the same as its maximum number of vertices
+            let index = if index == RESTART_INDEX { self.max_vertices() } else { index };
+            match self {
+                IndexFormat::U8 => res.push(index as u8),
+                IndexFormat::U16 => res.extend_from_slice(&(index as u16).to_le_bytes()),
+                IndexFormat::U32 => res.extend_from_slice(&index.to_le_bytes()),
+            }
+        }
+        res
+    }
+}
+
+/// A named part of a mesh, such as the faces of a model that use one material. Submeshes are
+/// recorded with `MeshBuilder::start_submesh` and drawn with `Mesh::draw_submesh`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Submesh {
+    pub name: String,
+    /// The indices (or vertices, if the mesh isn't indexed) that make up the submesh, as a
+    /// range that can be passed to `Mesh::draw_range`.
+    pub elements: Range<u32>,
+}
 
 /// A struct that builds a mesh from a collection of primitives.
 ///
 /// This struct only stores the mesh data and indices; to use it in OpenGL, it must be used to
 /// build a `Mesh`.
+///
+/// A `MeshBuilder` created with `non_indexed` doesn't store indices; its vertices are drawn
+/// in the order they were added, with `drawArrays`. This is useful for meshes where each
+/// vertex is only used once, such as fullscreen triangles and point clouds.
 pub struct MeshBuilder<V: Vertex, P: Primitive> {
-    vertex_data: Vec<f32>,
-    indices: Vec<MeshIndex>,
-    next_index: MeshIndex,
+    pub(crate) vertex_data: Vec<u8>,
+    pub(crate) indices: Vec<MeshIndex>,
+    pub(crate) next_index: MeshIndex,
+    index_format: Option<IndexFormat>,
+    indexed: bool,
+    // The name, first element, and end of each submesh. Only the last submesh can be open, with
+    // an end of `None`; it contains every element added since it was started.
+    submeshes: Vec<(String, u32, Option<u32>)>,
     phantom: PhantomData<(V, P)>,
 }
 
 impl<V: Vertex, P: Primitive> MeshBuilder<V, P> {
     pub fn new() -> Self {
-        MeshBuilder { vertex_data: vec![], indices: vec![], next_index: 0, phantom: PhantomData }
+        MeshBuilder {
+            vertex_data: vec![],
+            indices: vec![],
+            next_index: 0,
+            index_format: None,
+            indexed: true,
+            submeshes: vec![],
+            phantom: PhantomData,
+        }
+    }
+
+    /// Creates a `MeshBuilder` without indices. Primitives are formed from the vertices in the
+    /// order they're added, and the methods that add primitives by index can't be used.
+    pub fn non_indexed() -> Self {
+        MeshBuilder { indexed: false, ..Self::new() }
+    }
+
+    /// Returns false if the `MeshBuilder` was created with `non_indexed`.
+    pub fn is_indexed(&self) -> bool {
+        self.indexed
     }
 
     /// Adds a vertex to the mesh. The vertex won't be rendered unless it's used in a primitive
     /// (currently either `Triangles` or `Lines`, each of which adds a method to this struct to
     /// add the corresponding primitive).
     pub fn vert(&mut self, vert: V) -> MeshIndex {
-        assert!(self.next_index < MeshIndex::max_value());
+        assert!(self.next_index < MeshIndex::MAX);
         let index = self.next_index;
         self.next_index += 1;
-        vert.add_to_mesh(&mut |data| self.vertex_data.push(data));
+        vert.add_to_mesh(&mut |data| self.vertex_data.extend_from_slice(data));
         index
     }
 
@@ -116,24 +241,134 @@ impl<V: Vertex, P: Primitive> MeshBuilder<V, P> {
         self.vertex_data.clear();
         self.indices.clear();
         self.next_index = 0;
+        self.submeshes.clear();
+    }
+
+    /// Starts a submesh with the given name. Primitives added after this, up until the next call
+    /// to `start_submesh` or `extend`, belong to the submesh. Primitives added before the first
+    /// call don't belong to any submesh.
+    pub fn start_submesh(&mut self, name: &str) {
+        let start = self.num_elements();
+        self.end_submesh(start);
+        self.submeshes.push((name.to_string(), start, None));
+    }
+
+    /// Ends the open submesh, if there is one, at the given element.
+    fn end_submesh(&mut self, end: u32) {
+        if let Some((_, _, submesh_end @ None)) = self.submeshes.last_mut() {
+            *submesh_end = Some(end);
+        }
+    }
+
+    /// Returns the submeshes that have been started so far.
+    pub fn submeshes(&self) -> Vec<Submesh> {
+        let mut res = Vec::with_capacity(self.submeshes.len());
+        for (name, start, end) in &self.submeshes {
+            let end = end.unwrap_or_else(|| self.num_elements());
+            res.push(Submesh { name: name.clone(), elements: *start..end });
+        }
+        res
     }
 
-    /// Adds all vertices and primitives from the other mesh to this mesh.
+    /// Returns the number of indices, or of vertices if the `MeshBuilder` isn't indexed.
+    pub(crate) fn num_elements(&self) -> u32 {
+        if self.indexed {
+            self.indices.len() as u32
+        } else {
+            self.next_index
+        }
+    }
+
+    /// Adds all vertices and primitives from the other mesh to this mesh. Both must be indexed,
+    /// or neither. The other mesh's submeshes are kept.
+    ///
+    /// Indexed strips, fans, and loops from the two meshes are kept separate with a primitive
+    /// restart. Non-indexed ones can't be, so this panics if both meshes are non-indexed strips,
+    /// fans, or loops with vertices.
     pub fn extend(&mut self, other: MeshBuilder<V, P>) {
+        assert_eq!(self.indexed, other.indexed);
+        if P::IS_STRIP && !self.indexed && self.next_index > 0 && other.next_index > 0 {
+            error!(
+                "Can't extend a non-indexed strip, fan, or loop without joining it to the other \
+                 mesh's; use indexed meshes instead"
+            );
+            panic!();
+        }
         let start_index = self.next_index;
-        let num_verts = (other.vertex_data.len() / V::stride() as usize) as u16;
+        let start_element = if self.indexed { self.indices.len() as u32 } else { start_index };
+        let num_verts = (other.vertex_data.len() / V::stride() as usize) as MeshIndex;
         let num_verts2 = other.next_index;
         // TODO: remove this
         assert_eq!(num_verts as usize * V::stride() as usize, other.vertex_data.len());
         assert_eq!(num_verts, num_verts2);
         self.next_index += num_verts;
         self.vertex_data.extend(other.vertex_data);
-        self.indices.extend(other.indices.iter().map(|x| x + start_index));
+        let needs_restart = P::IS_STRIP && !self.indices.is_empty() && !other.indices.is_empty();
+        if needs_restart {
+            self.indices.push(RESTART_INDEX);
+        }
+        // This mesh's primitives end before the other mesh's, even if the other mesh has some
+        // that aren't in a submesh. The restart belongs to a submesh that starts at the other
+        // mesh's first element.
+        self.end_submesh(start_element);
+        let offset = |element| {
+            if element == 0 {
+                start_element
+            } else {
+                element + start_element + needs_restart as u32
+            }
+        };
+        for (name, start, end) in other.submeshes {
+            self.submeshes.push((name, offset(start), end.map(offset)));
+        }
+        self.indices.extend(other.indices.iter().map(|&x| {
+            if x == RESTART_INDEX {
+                RESTART_INDEX
+            } else {
+                x + start_index
+            }
+        }));
+    }
+
+    fn push_indices(&mut self, indices: &[MeshIndex]) {
+        assert!(self.indexed, "Can't add indices to a non-indexed mesh");
+        self.indices.extend_from_slice(indices);
+    }
+
+    /// Starts a new strip, fan, or loop containing the given indices.
+    fn push_strip(&mut self, indices: &[MeshIndex]) {
+        if !self.indices.is_empty() {
+            self.push_indices(&[RESTART_INDEX]);
+        }
+        self.push_indices(indices);
     }
 
     pub fn next_index(&self) -> MeshIndex {
         self.next_index
     }
+
+    /// Sets the format that the indices will be stored in when the mesh is built. By default,
+    /// or if this is `None`, the format is chosen with `IndexFormat::for_num_vertices`, so a
+    /// mesh that outgrows `u16` indices automatically switches to `u32`.
+    pub fn set_index_format(&mut self, index_format: Option<IndexFormat>) {
+        self.index_format = index_format;
+    }
+
+    /// Returns the format that the indices will be stored in when the mesh is built. Panics
+    /// if the format passed to `set_index_format` can't index all of the vertices.
+    pub fn index_format(&self) -> IndexFormat {
+        match self.index_format {
+            Some(index_format) => {
+                assert!(
+                    self.next_index <= index_format.max_vertices(),
+                    "Too many vertices for {:?} indices",
+                    index_format
+                );
+                index_format
+            }
+            None => IndexFormat::for_num_vertices(self.next_index),
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -143,12 +378,12 @@ impl Primitive for Triangles {
     const AS_GL: u32 = WebGl2::TRIANGLES;
 }
 
+impl FeedbackPrimitive for Triangles {}
+
 impl<V: Vertex> MeshBuilder<V, Triangles> {
     /// Adds a triangle to the mesh.
     pub fn triangle(&mut self, a: MeshIndex, b: MeshIndex, c: MeshIndex) {
-        self.indices.push(a);
-        self.indices.push(b);
-        self.indices.push(c);
+        self.push_indices(&[a, b, c]);
     }
 }
 
@@ -159,11 +394,12 @@ impl Primitive for Lines {
     const AS_GL: u32 = WebGl2::LINES;
 }
 
+impl FeedbackPrimitive for Lines {}
+
 impl<V: Vertex> MeshBuilder<V, Lines> {
     /// Adds a line to the mesh.
     pub fn line(&mut self, a: MeshIndex, b: MeshIndex) {
-        self.indices.push(a);
-        self.indices.push(b);
+        self.push_indices(&[a, b]);
     }
 }
 
@@ -174,31 +410,113 @@ impl Primitive for Points {
     const AS_GL: u32 = WebGl2::POINTS;
 }
 
+impl FeedbackPrimitive for Points {}
+
 impl<V: Vertex> MeshBuilder<V, Points> {
     /// Adds a point to the mesh.
     pub fn point(&mut self, a: MeshIndex) {
-        self.indices.push(a);
+        self.push_indices(&[a]);
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct TriangleStrip;
+
+impl Primitive for TriangleStrip {
+    const AS_GL: u32 = WebGl2::TRIANGLE_STRIP;
+    const IS_STRIP: bool = true;
+}
+
+impl<V: Vertex> MeshBuilder<V, TriangleStrip> {
+    /// Adds a triangle strip to the mesh. It isn't connected to the previous strip.
+    pub fn triangle_strip(&mut self, indices: &[MeshIndex]) {
+        self.push_strip(indices);
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct TriangleFan;
+
+impl Primitive for TriangleFan {
+    const AS_GL: u32 = WebGl2::TRIANGLE_FAN;
+    const IS_STRIP: bool = true;
+}
+
+impl<V: Vertex> MeshBuilder<V, TriangleFan> {
+    /// Adds a triangle fan to the mesh. The first index is the center of the fan.
+    pub fn triangle_fan(&mut self, indices: &[MeshIndex]) {
+        self.push_strip(indices);
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct LineStrip;
+
+impl Primitive for LineStrip {
+    const AS_GL: u32 = WebGl2::LINE_STRIP;
+    const IS_STRIP: bool = true;
+}
+
+impl<V: Vertex> MeshBuilder<V, LineStrip> {
+    /// Adds a line strip to the mesh. It isn't connected to the previous strip.
+    pub fn line_strip(&mut self, indices: &[MeshIndex]) {
+        self.push_strip(indices);
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct LineLoop;
+
+impl Primitive for LineLoop {
+    const AS_GL: u32 = WebGl2::LINE_LOOP;
+    const IS_STRIP: bool = true;
+}
+
+impl<V: Vertex> MeshBuilder<V, LineLoop> {
+    /// Adds a line loop to the mesh; the last index is connected back to the first.
+    pub fn line_loop(&mut self, indices: &[MeshIndex]) {
+        self.push_strip(indices);
     }
 }
 
 /// A mesh; built using a `MeshBuilder`.
+///
+/// After it's built, parts of the mesh can be changed with `update_vertices` and
+/// `update_indices` without uploading the whole mesh again. The mesh's buffers grow
+/// geometrically, so appending to a mesh every frame rarely needs to reallocate. Attributes
+/// that change more often than the rest can be moved to a `VertexStream`.
+///
+/// A mesh is usually drawn with the program it was created with, but it can also be drawn with
+/// any other program that uses the same vertex type, such as for a shadow or picking pass, with
+/// `draw_with_program`. Attributes that the other program doesn't use are ignored.
 pub struct Mesh<V: Vertex, U: GlUniforms, P: Primitive> {
     vao: WebGlVertexArrayObject,
-    vbo: WebGlBuffer,
-    ibo: WebGlBuffer,
+    vbo: GlBuffer,
+    ibo: GlBuffer,
     context: GlContext,
     program: GlProgram<V, U>,
-    num_indices: i32,
+    // The number of indices, or of vertices if the mesh isn't indexed
+    num_elements: i32,
+    // `None` if the mesh isn't indexed
+    index_format: Option<IndexFormat>,
+    num_vertices: u32,
+    submeshes: Vec<Submesh>,
     phantom: PhantomData<P>,
     // TODO: can this be inferred from the vertex/uniforms types?
     draw_mode: DrawMode,
+    // The buffer, attribute setup, and vertex size of each stream passed to `attach_stream`.
+    // The buffers are shared with the streams, so they stay alive as long as the mesh does.
+    streams: Vec<(Rc<RefCell<GlBuffer>>, SetupVertexAttribs, usize)>,
+    // VAOs for programs other than `program`, created by `draw_with_program` as needed
+    program_vaos: RefCell<HashMap<ProgramId, WebGlVertexArrayObject>>,
 }
 
 impl<V: Vertex, U: GlUniforms, P: Primitive> Drop for Mesh<V, U, P> {
     fn drop(&mut self) {
         self.context.inner.delete_vertex_array(Some(&self.vao));
-        self.context.inner.delete_buffer(Some(&self.vbo));
-        self.context.inner.delete_buffer(Some(&self.ibo));
+        for vao in self.program_vaos.borrow().values() {
+            self.context.inner.delete_vertex_array(Some(vao));
+        }
     }
 }
 
@@ -208,10 +526,10 @@ impl<V: Vertex, U: GlUniforms, P: Primitive> Mesh<V, U, P> {
         let vao = context.inner.create_vertex_array().unwrap();
         context.inner.bind_vertex_array(Some(&vao));
 
-        let vbo = context.inner.create_buffer().unwrap();
-        let ibo = context.inner.create_buffer().unwrap();
-        context.inner.bind_buffer(WebGl2::ARRAY_BUFFER, Some(&vbo));
-        context.inner.bind_buffer(WebGl2::ELEMENT_ARRAY_BUFFER, Some(&ibo));
+        let vbo = GlBuffer::new(context, WebGl2::ARRAY_BUFFER, MeshUsage::StaticDraw);
+        let ibo = GlBuffer::new(context, WebGl2::ELEMENT_ARRAY_BUFFER, MeshUsage::StaticDraw);
+        vbo.bind();
+        ibo.bind();
 
         Mesh {
             vao,
@@ -219,48 +537,222 @@ impl<V: Vertex, U: GlUniforms, P: Primitive> Mesh<V, U, P> {
             ibo,
             context: context.clone(),
             program: program.clone(),
-            num_indices: 0,
+            num_elements: 0,
+            index_format: None,
+            num_vertices: 0,
+            submeshes: vec![],
             phantom: PhantomData,
             draw_mode,
+            streams: vec![],
+            program_vaos: RefCell::new(HashMap::new()),
         }
     }
 
     /// Clears the mesh's current contents and updates it with the contents of the `MeshBuilder`.
+    /// The mesh's existing buffers are reused if they're large enough and `usage` hasn't changed.
     pub fn build_from(&mut self, builder: &MeshBuilder<V, P>, usage: MeshUsage) {
-        self.num_indices = builder.indices.len() as i32;
-        if self.num_indices == 0 {
-            return;
+        self.num_elements = builder.num_elements() as i32;
+        self.num_vertices = builder.next_index;
+        self.submeshes = builder.submeshes();
+
+        self.bind();
+
+        setup_vertex_attribs::<V>(
+            &self.context,
+            &self.program.inner.state.borrow().objects.program,
+            false,
+            0,
+        );
+
+        self.vbo.set_usage(usage);
+        self.vbo.set_data(&builder.vertex_data);
+
+        if builder.indexed {
+            let index_format = builder.index_format();
+            self.ibo.set_usage(usage);
+            self.ibo.set_data(&index_format.convert(&builder.indices));
+            self.index_format = Some(index_format);
+        } else {
+            self.ibo.truncate(0);
+            self.index_format = None;
+        }
+    }
+
+    /// Reads the attributes in `S` from the `VertexStream` rather than from the mesh's own
+    /// vertex buffer, which then only needs to contain the rest of the attributes. The stream
+    /// stays attached until the mesh is dropped, and can be updated without affecting the mesh's
+    /// other vertex data. Drawing the mesh panics if the stream has fewer vertices than it.
+    pub fn attach_stream<S: Vertex>(&mut self, stream: &VertexStream<S>) {
+        let setup: SetupVertexAttribs = setup_vertex_attribs::<S>;
+        self.context.inner.bind_vertex_array(Some(&self.vao));
+        stream.buffer.borrow().bind();
+        setup(&self.context, &self.program.inner.state.borrow().objects.program, false, 0);
+        self.streams.push((stream.buffer.clone(), setup, S::stride() as usize));
+
+        // VAOs created for other programs are recreated with the stream when they're next used
+        for vao in self.program_vaos.borrow_mut().drain().map(|(_, vao)| vao) {
+            self.context.inner.delete_vertex_array(Some(&vao));
+        }
+    }
+
+    /// Replaces the vertices starting at index `start` with `verts`, using `bufferSubData`.
+    /// `start` can be at most the number of vertices in the mesh; vertices past the end are
+    /// appended. For an indexed mesh, the new vertices aren't drawn until indices that use them
+    /// are added with `update_indices`, and the number of vertices can't exceed what its
+    /// `IndexFormat` can index.
+    pub fn update_vertices(&mut self, start: MeshIndex, verts: &[V]) {
+        self.write_vertices(start, verts.len(), &vertex_bytes(verts));
+    }
+
+    /// Writes `num_verts` vertices, already converted to bytes, starting at index `start`.
+    pub(crate) fn write_vertices(&mut self, start: MeshIndex, num_verts: usize, data: &[u8]) {
+        assert!(start <= self.num_vertices, "Vertex {} is past the end of the mesh", start);
+        let end = start as usize + num_verts;
+        if let Some(index_format) = self.index_format {
+            assert!(
+                end <= index_format.max_vertices() as usize,
+                "The mesh's index format ({:?}) can't index {} vertices",
+                index_format,
+                end
+            );
         }
 
         self.bind();
+        self.vbo.write(start as usize * V::stride() as usize, data);
 
-        setup_vertex_attribs::<V, _, _>(&self.program, false);
+        self.num_vertices = self.num_vertices.max(end as MeshIndex);
+        if self.index_format.is_none() {
+            self.num_elements = self.num_vertices as i32;
+        }
+    }
 
-        let memory_buffer = memory().dyn_into::<Memory>().unwrap().buffer();
+    /// Replaces the indices starting at position `start` with `indices`, using `bufferSubData`.
+    /// `start` can be at most the number of indices in the mesh; indices past the end are
+    /// appended. Panics if the mesh isn't indexed, or if an index refers to a vertex that
+    /// doesn't exist.
+    pub fn update_indices(&mut self, start: u32, indices: &[MeshIndex]) {
+        let index_format = match self.index_format {
+            Some(index_format) => index_format,
+            None => {
+                error!("Can't update the indices of a non-indexed mesh");
+                panic!();
+            }
+        };
+        assert!(start <= self.num_elements as u32, "Index {} is past the end of the mesh", start);
+        for &index in indices {
+            assert!(
+                index < self.num_vertices || index == RESTART_INDEX,
+                "Index {} refers to a vertex that isn't in the mesh",
+                index
+            );
+        }
 
-        let vertex_data_loc = builder.vertex_data.as_ptr() as u32 / 4;
-        let vertex_array = Float32Array::new(&memory_buffer)
-            .subarray(vertex_data_loc, vertex_data_loc + builder.vertex_data.len() as u32);
-        self.context.inner.buffer_data_with_array_buffer_view(
-            WebGl2::ARRAY_BUFFER,
-            &vertex_array,
-            usage.as_gl(),
+        self.bind();
+        self.ibo.write(
+            start as usize * index_format.size_in_bytes() as usize,
+            &index_format.convert(indices),
         );
+        self.num_elements = self.num_elements.max((start as usize + indices.len()) as i32);
+    }
 
-        let indices_loc = builder.indices.as_ptr() as u32 / 2;
-        let index_array = Uint16Array::new(&memory_buffer)
-            .subarray(indices_loc, indices_loc + builder.indices.len() as u32);
-        self.context.inner.buffer_data_with_array_buffer_view(
-            WebGl2::ELEMENT_ARRAY_BUFFER,
-            &index_array,
-            usage.as_gl(),
-        );
+    /// Returns the number of vertices in the mesh.
+    pub fn num_vertices(&self) -> u32 {
+        self.num_vertices
+    }
+
+    /// Returns the number of indices in the mesh, or the number of vertices if it isn't indexed.
+    pub fn num_elements(&self) -> u32 {
+        self.num_elements as u32
+    }
+
+    /// Returns the format that the mesh's indices are stored in, or `None` if it isn't indexed.
+    pub fn index_format(&self) -> Option<IndexFormat> {
+        self.index_format
+    }
+
+    /// Returns the mesh's submeshes, as recorded by the `MeshBuilder` it was built from.
+    pub fn submeshes(&self) -> &[Submesh] {
+        &self.submeshes
+    }
+
+    /// Returns the submesh with the given name, if there is one.
+    pub fn submesh(&self, name: &str) -> Option<&Submesh> {
+        self.submeshes.iter().find(|submesh| submesh.name == name)
+    }
+
+    /// Draws a range of the mesh's primitives, with `drawRangeElements` or `drawArrays`
+    /// depending on whether it's indexed. If `num_instances` is given, the instanced version is
+    /// used.
+    fn draw_primitives(&self, elements: Range<u32>, num_instances: Option<i32>) {
+        assert!(elements.start <= elements.end && elements.end <= self.num_elements as u32);
+        for (buffer, _, stride) in &self.streams {
+            let stream_len = buffer.borrow().len() / stride;
+            if stream_len < self.num_vertices as usize {
+                error!(
+                    "A vertex stream has {} vertices, but the mesh it's attached to has {}",
+                    stream_len, self.num_vertices
+                );
+                panic!();
+            }
+        }
+        let count = (elements.end - elements.start) as i32;
+        let inner = &self.context.inner;
+        match (self.index_format, num_instances) {
+            (Some(index_format), None) => inner.draw_range_elements_with_i32(
+                P::AS_GL,
+                0,
+                self.num_vertices.saturating_sub(1),
+                count,
+                index_format.as_gl(),
+                elements.start as i32 * index_format.size_in_bytes(),
+            ),
+            (Some(index_format), Some(num_instances)) => inner.draw_elements_instanced_with_i32(
+                P::AS_GL,
+                count,
+                index_format.as_gl(),
+                elements.start as i32 * index_format.size_in_bytes(),
+                num_instances,
+            ),
+            (None, None) => inner.draw_arrays(P::AS_GL, elements.start as i32, count),
+            (None, Some(num_instances)) => {
+                inner.draw_arrays_instanced(P::AS_GL, elements.start as i32, count, num_instances)
+            }
+        }
+    }
+
+    /// Returns the VAO that sets up the mesh's attributes for the given program, creating it
+    /// if necessary.
+    fn vao_for<U2: GlUniforms>(&self, program: &GlProgram<V, U2>) -> WebGlVertexArrayObject {
+        if program.inner.id == self.program.inner.id {
+            return self.vao.clone();
+        }
+        if let Some(vao) = self.program_vaos.borrow().get(&program.inner.id) {
+            return vao.clone();
+        }
+
+        let inner = &self.context.inner;
+        let state = program.inner.state.borrow();
+        let vao = inner.create_vertex_array().unwrap();
+        inner.bind_vertex_array(Some(&vao));
+        self.vbo.bind();
+        setup_vertex_attribs::<V>(&self.context, &state.objects.program, false, 0);
+        self.ibo.bind();
+        for (buffer, setup, _) in &self.streams {
+            buffer.borrow().bind();
+            setup(&self.context, &state.objects.program, false, 0);
+        }
+        self.program_vaos.borrow_mut().insert(program.inner.id, vao.clone());
+        vao
     }
 
     fn bind(&self) {
-        self.context.inner.bind_vertex_array(Some(&self.vao));
+        self.bind_vao(&self.vao);
+    }
+
+    fn bind_vao(&self, vao: &WebGlVertexArrayObject) {
+        self.context.inner.bind_vertex_array(Some(vao));
         // The ELEMENT_ARRAY_BUFFER doesn't need to be bound here, but the ARRAY_BUFFER does (https://stackoverflow.com/a/21652930)
-        self.context.inner.bind_buffer(WebGl2::ARRAY_BUFFER, Some(&self.vbo));
+        self.vbo.bind();
     }
 
     /// Draws the mesh.
@@ -269,116 +761,346 @@ impl<V: Vertex, U: GlUniforms, P: Primitive> Mesh<V, U, P> {
         surface: &(impl Surface + ?Sized),
         uniforms: &impl Uniforms<GlUniforms = U>,
     ) {
-        if self.num_indices == 0 {
+        self.draw_range(surface, uniforms, 0..self.num_elements as u32);
+    }
+
+    /// Draws the submesh with the given name. Panics if the mesh doesn't have a submesh with
+    /// that name.
+    pub fn draw_submesh(
+        &self,
+        surface: &(impl Surface + ?Sized),
+        uniforms: &impl Uniforms<GlUniforms = U>,
+        name: &str,
+    ) {
+        let elements = match self.submesh(name) {
+            Some(submesh) => submesh.elements.clone(),
+            None => {
+                error!("Mesh has no submesh named {}", name);
+                panic!();
+            }
+        };
+        self.draw_range(surface, uniforms, elements);
+    }
+
+    /// Draws part of the mesh: the primitives formed by the given range of indices, or of
+    /// vertices if the mesh isn't indexed. Each range shares the mesh's buffers, so different
+    /// parts of a mesh can be drawn with different uniforms without rebuilding anything.
+    pub fn draw_range(
+        &self,
+        surface: &(impl Surface + ?Sized),
+        uniforms: &impl Uniforms<GlUniforms = U>,
+        elements: Range<u32>,
+    ) {
+        self.draw_range_with_program(surface, &self.program, uniforms, elements);
+    }
+
+    /// Draws the mesh with a program other than the one it was created with. The first time
+    /// each program is used, a VAO is created for it and cached in the mesh.
+    pub fn draw_with_program<U2: GlUniforms>(
+        &self,
+        surface: &(impl Surface + ?Sized),
+        program: &GlProgram<V, U2>,
+        uniforms: &impl Uniforms<GlUniforms = U2>,
+    ) {
+        self.draw_range_with_program(surface, program, uniforms, 0..self.num_elements as u32);
+    }
+
+    /// Like `draw_range`, but with a program other than the one the mesh was created with.
+    pub fn draw_range_with_program<U2: GlUniforms>(
+        &self,
+        surface: &(impl Surface + ?Sized),
+        program: &GlProgram<V, U2>,
+        uniforms: &impl Uniforms<GlUniforms = U2>,
+        elements: Range<u32>,
+    ) {
+        if elements.start == elements.end {
             return;
         }
 
         // TODO: state caching
-        self.bind();
-        self.program.bind(&self.context);
-        uniforms.update(&self.context, &self.program.inner.gl_uniforms);
+        self.bind_vao(&self.vao_for(program));
+        let bound_program = program.bind(&self.context);
+        uniforms.update(&bound_program);
         surface.bind(&self.context);
         self.draw_mode.bind(&self.context);
 
-        self.context.inner.draw_elements_with_i32(
-            P::AS_GL,
-            self.num_indices,
-            WebGl2::UNSIGNED_SHORT,
-            0,
-        );
+        self.draw_primitives(elements, None);
     }
 
     /// Draws the mesh using instanced rendering. Like `draw()`, but several instances
     /// can be passed in the `instances` parameter and the mesh will be drawn once for each
     /// instance. The instance data's fields must be in the same order as its `VertexData` impl
     /// specifies, and it must use `#[repr(C)]`.
+    ///
+    /// The instances are uploaded on every call; for instances that rarely change, use an
+    /// `InstanceBuffer` with `draw_instanced_buffer` instead.
     pub fn draw_instanced<I: VertexData>(
         &self,
         surface: &(impl Surface + ?Sized),
         uniforms: &impl Uniforms<GlUniforms = U>,
         instances: &[I],
     ) {
-        if self.num_indices == 0 || instances.is_empty() {
+        if self.num_elements == 0 || instances.is_empty() {
             return;
         }
 
-        // TODO: state caching
-        self.bind();
-        self.program.bind(&self.context);
-        uniforms.update(&self.context, &self.program.inner.gl_uniforms);
-        surface.bind(&self.context);
-        self.draw_mode.bind(&self.context);
-
-        setup_vertex_attribs::<I, _, _>(&self.program, true);
-
         let memory_buffer = memory().dyn_into::<Memory>().unwrap().buffer();
 
-        let vertex_data_loc = instances.as_ptr() as u32 / 4;
-        let vertex_array = Float32Array::new(&memory_buffer).subarray(
+        debug_assert_eq!(mem::size_of::<I>(), I::stride() as usize);
+        let vertex_data_loc = instances.as_ptr() as u32;
+        let vertex_array = Uint8Array::new(&memory_buffer).subarray(
             vertex_data_loc,
             vertex_data_loc + instances.len() as u32 * I::stride() as u32,
         );
+        self.context.inner.bind_buffer(WebGl2::ARRAY_BUFFER, Some(&self.context.instanced_vbo));
         self.context.inner.buffer_data_with_array_buffer_view(
             WebGl2::ARRAY_BUFFER,
             &vertex_array,
-            // TODO: what usage should be used here?
             MeshUsage::StreamDraw.as_gl(),
         );
 
-        self.context.inner.draw_elements_instanced_with_i32(
-            P::AS_GL,
-            self.num_indices,
-            WebGl2::UNSIGNED_SHORT,
-            0,
+        self.draw_with_instances::<I>(
+            surface,
+            uniforms,
+            &self.context.instanced_vbo,
             instances.len() as i32,
         );
     }
-}
 
-fn setup_vertex_attribs<D: VertexData, V: Vertex, U: GlUniforms>(
-    program: &GlProgram<V, U>,
-    instanced: bool,
-) {
-    let context = &program.inner.context;
-    let stride = D::stride();
-    let mut offset = 0;
-    for (attr, size) in D::ATTRIBUTES.iter() {
-        let loc = context.inner.get_attrib_location(&program.inner.program, attr) as u32;
-
-        // Matrices take up 4 attributes so each row has to be specified separately.
-        if *size == 16 {
-            setup_vertex_attrib(context, loc, 4, stride, offset, instanced);
-            setup_vertex_attrib(context, loc + 1, 4, stride, offset + 4, instanced);
-            setup_vertex_attrib(context, loc + 2, 4, stride, offset + 8, instanced);
-            setup_vertex_attrib(context, loc + 3, 4, stride, offset + 12, instanced);
-        } else if *size <= 4 {
-            setup_vertex_attrib(context, loc, *size, stride, offset, instanced);
-        } else {
-            panic!("Unsupported vertex data size");
+    /// Draws the mesh once for each instance in the `InstanceBuffer`. Unlike `draw_instanced`,
+    /// nothing is uploaded.
+    pub fn draw_instanced_buffer<I: VertexData>(
+        &self,
+        surface: &(impl Surface + ?Sized),
+        uniforms: &impl Uniforms<GlUniforms = U>,
+        instances: &InstanceBuffer<I>,
+    ) {
+        if self.num_elements == 0 || instances.is_empty() {
+            return;
         }
 
-        offset += size;
+        self.draw_with_instances::<I>(
+            surface,
+            uniforms,
+            &instances.buffer.buffer,
+            instances.len() as i32,
+        );
+    }
+
+    /// Draws the mesh with instance attributes read from `instance_buffer`.
+    fn draw_with_instances<I: VertexData>(
+        &self,
+        surface: &(impl Surface + ?Sized),
+        uniforms: &impl Uniforms<GlUniforms = U>,
+        instance_buffer: &WebGlBuffer,
+        num_instances: i32,
+    ) {
+        // TODO: state caching
+        self.bind();
+        let bound_program = self.program.bind(&self.context);
+        uniforms.update(&bound_program);
+        surface.bind(&self.context);
+        self.draw_mode.bind(&self.context);
+
+        // The instance attributes are stored in the VAO, so they refer to this buffer until the
+        // next instanced draw
+        self.context.inner.bind_buffer(WebGl2::ARRAY_BUFFER, Some(instance_buffer));
+        setup_vertex_attribs::<I>(
+            &self.context,
+            &self.program.inner.state.borrow().objects.program,
+            true,
+            0,
+        );
+
+        self.draw_primitives(0..self.num_elements as u32, Some(num_instances));
     }
 }
 
-fn setup_vertex_attrib(
-    context: &GlContext,
-    loc: u32,
-    size: i32,
-    stride: i32,
-    offset: i32,
-    instanced: bool,
-) {
-    context.inner.enable_vertex_attrib_array(loc);
-    context.inner.vertex_attrib_pointer_with_i32(
-        loc,
-        size,
-        WebGl2::FLOAT,
-        false,
-        stride * 4,
-        offset * 4,
-    );
-    if instanced {
-        context.inner.vertex_attrib_divisor(loc, 1);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone)]
+    struct TestVertex(f32);
+
+    impl VertexData for TestVertex {
+        const ATTRIBUTES: Attributes = &[Attribute::f32("value", AttributeType::Scalar)];
+    }
+
+    impl VertexComponent for TestVertex {
+        fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+            self.0.add_to_mesh(f);
+        }
+    }
+
+    #[test]
+    fn index_format_max_vertices() {
+        // The largest index of each format is reserved for primitive restart
+        assert_eq!(IndexFormat::U8.max_vertices(), 255);
+        assert_eq!(IndexFormat::U16.max_vertices(), 65535);
+        assert_eq!(IndexFormat::U32.max_vertices(), u32::MAX);
+    }
+
+    #[test]
+    fn index_format_for_num_vertices() {
+        assert_eq!(IndexFormat::for_num_vertices(0), IndexFormat::U16);
+        assert_eq!(IndexFormat::for_num_vertices(65535), IndexFormat::U16);
+        assert_eq!(IndexFormat::for_num_vertices(65536), IndexFormat::U32);
+    }
+
+    #[test]
+    fn index_format_convert() {
+        let indices = [0, 1, 254, RESTART_INDEX, 2];
+        assert_eq!(IndexFormat::U8.convert(&indices), [0, 1, 254, 255, 2]);
+
+        let indices = [0, 0x1234, 65534, RESTART_INDEX];
+        assert_eq!(IndexFormat::U16.convert(&indices), [0, 0, 0x34, 0x12, 0xfe, 0xff, 0xff, 0xff]);
+
+        let indices = [0x12345678, RESTART_INDEX];
+        assert_eq!(
+            IndexFormat::U32.convert(&indices),
+            [0x78, 0x56, 0x34, 0x12, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn builder_index_format() {
+        let mut builder = MeshBuilder::<TestVertex, Triangles>::new();
+        assert_eq!(builder.index_format(), IndexFormat::U16);
+        builder.next_index = 65536;
+        assert_eq!(builder.index_format(), IndexFormat::U32);
+
+        builder.next_index = 255;
+        builder.set_index_format(Some(IndexFormat::U8));
+        assert_eq!(builder.index_format(), IndexFormat::U8);
+    }
+
+    fn triangles(num_triangles: u32) -> MeshBuilder<TestVertex, Triangles> {
+        let mut builder = MeshBuilder::new();
+        for _ in 0..num_triangles {
+            builder.add_triangle();
+        }
+        builder
+    }
+
+    impl MeshBuilder<TestVertex, Triangles> {
+        fn add_triangle(&mut self) {
+            let a = self.vert(TestVertex(0.0));
+            let b = self.vert(TestVertex(1.0));
+            let c = self.vert(TestVertex(2.0));
+            self.triangle(a, b, c);
+        }
+    }
+
+    fn submesh(name: &str, elements: Range<u32>) -> Submesh {
+        Submesh { name: name.to_string(), elements }
+    }
+
+    #[test]
+    fn submeshes() {
+        let mut builder = triangles(1);
+        builder.start_submesh("a");
+        builder.add_triangle();
+        builder.start_submesh("b");
+        builder.add_triangle();
+        builder.add_triangle();
+        assert_eq!(builder.submeshes(), [submesh("a", 3..6), submesh("b", 6..12)]);
+    }
+
+    #[test]
+    fn extend_submeshes() {
+        let mut builder = triangles(0);
+        builder.start_submesh("a");
+        builder.add_triangle();
+
+        // The other mesh's first triangle isn't in a submesh, so it isn't added to "a"
+        let mut other = triangles(1);
+        other.start_submesh("b");
+        other.add_triangle();
+        builder.extend(other);
+        assert_eq!(builder.submeshes(), [submesh("a", 0..3), submesh("b", 6..9)]);
+
+        // The other mesh's last submesh is still open
+        builder.add_triangle();
+        assert_eq!(builder.submeshes(), [submesh("a", 0..3), submesh("b", 6..12)]);
+    }
+
+    #[test]
+    fn extend_strip_submeshes() {
+        let mut builder = strip(3);
+        let mut other = MeshBuilder::new();
+        other.start_submesh("a");
+        other.triangle_strip(&[0, 0, 0]);
+        other.start_submesh("b");
+        other.triangle_strip(&[0, 0, 0]);
+        builder.extend(other);
+        // The first submesh includes the restart that separates it from this mesh's strip
+        assert_eq!(builder.submeshes(), [submesh("a", 3..7), submesh("b", 7..11)]);
+    }
+
+    fn strip(verts: u32) -> MeshBuilder<TestVertex, TriangleStrip> {
+        let mut builder = MeshBuilder::new();
+        let indices: Vec<_> = (0..verts).map(|i| builder.vert(TestVertex(i as f32))).collect();
+        builder.triangle_strip(&indices);
+        builder
+    }
+
+    #[test]
+    fn extend_indexed_strips_adds_restart() {
+        let mut builder = strip(3);
+        builder.extend(strip(4));
+        assert_eq!(builder.indices, [0, 1, 2, RESTART_INDEX, 3, 4, 5, 6]);
+        assert_eq!(builder.next_index, 7);
+
+        // No restart is needed when either mesh is empty
+        let mut builder = MeshBuilder::new();
+        builder.extend(strip(3));
+        builder.extend(MeshBuilder::new());
+        assert_eq!(builder.indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn extend_keeps_restarts() {
+        let mut other = strip(3);
+        other.triangle_strip(&[0, 1, 2]);
+        let mut builder = strip(3);
+        builder.extend(other);
+        assert_eq!(builder.indices, [0, 1, 2, RESTART_INDEX, 3, 4, 5, RESTART_INDEX, 3, 4, 5]);
+    }
+
+    #[test]
+    fn extend_non_indexed() {
+        let mut builder = MeshBuilder::<TestVertex, Triangles>::non_indexed();
+        builder.verts(vec![TestVertex(0.0); 3]);
+        let mut other = MeshBuilder::non_indexed();
+        other.verts(vec![TestVertex(1.0); 3]);
+        builder.extend(other);
+        assert_eq!(builder.num_elements(), 6);
+        assert!(builder.indices.is_empty());
+
+        // Extending with an empty strip doesn't join anything
+        let mut builder = MeshBuilder::<TestVertex, TriangleStrip>::non_indexed();
+        builder.verts(vec![TestVertex(0.0); 3]);
+        builder.extend(MeshBuilder::non_indexed());
+        assert_eq!(builder.num_elements(), 3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn extend_non_indexed_strips() {
+        let mut builder = MeshBuilder::<TestVertex, TriangleStrip>::non_indexed();
+        builder.verts(vec![TestVertex(0.0); 3]);
+        let mut other = MeshBuilder::non_indexed();
+        other.verts(vec![TestVertex(1.0); 3]);
+        builder.extend(other);
+    }
+
+    #[test]
+    #[should_panic]
+    fn builder_index_format_too_small() {
+        let mut builder = MeshBuilder::<TestVertex, Triangles>::new();
+        builder.next_index = 256;
+        builder.set_index_format(Some(IndexFormat::U8));
+        builder.index_format();
     }
 }
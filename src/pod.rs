@@ -0,0 +1,204 @@
+use bytemuck::Pod;
+use log::*;
+use std::mem;
+use std::ops::Range;
+
+use crate::mesh::*;
+use crate::uniforms::*;
+use crate::vertex::*;
+
+/// Checks that the in-memory layout of `V` is the same as the vertex buffer layout described by
+/// its `ATTRIBUTES`, so its bytes can be copied straight into a vertex buffer.
+///
+/// The field order is checked by filling a vertex with a different value in each byte, and
+/// comparing its bytes to what `add_to_mesh` produces for it.
+fn check_pod_layout<V: Vertex + Pod>() {
+    if mem::size_of::<V>() != V::stride() as usize {
+        error!(
+            "Vertex type has size {}, but its attributes have a total size of {}; it must use \
+             #[repr(C)] and have a field for each attribute",
+            mem::size_of::<V>(),
+            V::stride()
+        );
+        panic!();
+    }
+    // Vertices larger than 256 bytes repeat the pattern, which is unlikely to hide a problem
+    let bytes: Vec<u8> = (0..mem::size_of::<V>()).map(|i| i as u8).collect();
+    let sample: V = bytemuck::pod_read_unaligned(&bytes);
+    if vertex_bytes(std::slice::from_ref(&sample)) != bytes {
+        error!(
+            "Vertex type's fields aren't in the same order as its attributes; the order of the \
+             fields must match ATTRIBUTES"
+        );
+        panic!();
+    }
+}
+
+impl<V: Vertex + Pod, P: Primitive> MeshBuilder<V, P> {
+    /// Creates a `MeshBuilder` from vertices and indices that are already laid out in memory,
+    /// such as those loaded from a model file. The vertices are copied as a whole rather than
+    /// one component at a time. This requires the `bytemuck` feature.
+    ///
+    /// `V` must use `#[repr(C)]`, and its fields must be in the same order as its `ATTRIBUTES`.
+    /// Panics if an index refers to a vertex that isn't in `vertices`.
+    ///
+    /// Example usage:
+    /// ```
+    /// #[repr(C)]
+    /// #[derive(Copy, Clone, Pod, Zeroable)]
+    /// struct ModelVertex {
+    ///     pos: [f32; 3],
+    ///     normal: [f32; 3],
+    /// }
+    ///
+    /// let builder = MeshBuilder::<ModelVertex, Triangles>::from_slices(&model.vertices, &model.indices);
+    /// ```
+    pub fn from_slices(vertices: &[V], indices: &[MeshIndex]) -> Self {
+        let mut res = Self::new();
+        res.verts_from_slice(vertices);
+        for &index in indices {
+            if index >= res.next_index && index != RESTART_INDEX {
+                error!("Index {} refers to a vertex that isn't in the mesh", index);
+                panic!();
+            }
+        }
+        res.indices.extend_from_slice(indices);
+        res
+    }
+
+    /// Adds vertices to the mesh by copying the whole slice, returning their indices. This is
+    /// faster than calling `vert` for each vertex. This requires the `bytemuck` feature.
+    pub fn verts_from_slice(&mut self, verts: &[V]) -> Range<MeshIndex> {
+        check_pod_layout::<V>();
+        let start = self.next_index;
+        assert!(verts.len() < (MeshIndex::MAX - start) as usize);
+        self.next_index += verts.len() as MeshIndex;
+        self.vertex_data.extend_from_slice(bytemuck::cast_slice(verts));
+        start..self.next_index
+    }
+}
+
+impl<V: Vertex + Pod, U: GlUniforms, P: Primitive> Mesh<V, U, P> {
+    /// Like `update_vertices`, but uploads the slice directly rather than converting each
+    /// vertex first. This requires the `bytemuck` feature.
+    pub fn update_vertices_from_slice(&mut self, start: MeshIndex, verts: &[V]) {
+        check_pod_layout::<V>();
+        self.write_vertices(start, verts.len(), bytemuck::cast_slice(verts));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    struct TestVertex {
+        pos: [f32; 3],
+        color: [u8; 4],
+    }
+
+    unsafe impl Zeroable for TestVertex {}
+    unsafe impl Pod for TestVertex {}
+
+    impl VertexData for TestVertex {
+        const ATTRIBUTES: Attributes = &[
+            Attribute::f32("pos", AttributeType::Vec3),
+            Attribute::new(
+                "color",
+                AttributeType::Vec4,
+                ComponentType::U8,
+                AttributeConversion::Normalized,
+            ),
+        ];
+    }
+
+    impl VertexComponent for TestVertex {
+        fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+            self.pos.add_to_mesh(f);
+            self.color.add_to_mesh(f);
+        }
+    }
+
+    // The fields are in a different order than the attributes, but each attribute has the same
+    // size, so only the field order check catches it
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    struct SwappedVertex {
+        a: [f32; 2],
+        b: [f32; 2],
+    }
+
+    unsafe impl Zeroable for SwappedVertex {}
+    unsafe impl Pod for SwappedVertex {}
+
+    impl VertexData for SwappedVertex {
+        const ATTRIBUTES: Attributes =
+            &[Attribute::f32("b", AttributeType::Vec2), Attribute::f32("a", AttributeType::Vec2)];
+    }
+
+    impl VertexComponent for SwappedVertex {
+        fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+            self.b.add_to_mesh(f);
+            self.a.add_to_mesh(f);
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    struct MissingAttributeVertex {
+        pos: [f32; 2],
+        extra: f32,
+    }
+
+    unsafe impl Zeroable for MissingAttributeVertex {}
+    unsafe impl Pod for MissingAttributeVertex {}
+
+    impl VertexData for MissingAttributeVertex {
+        const ATTRIBUTES: Attributes = &[Attribute::f32("pos", AttributeType::Vec2)];
+    }
+
+    impl VertexComponent for MissingAttributeVertex {
+        fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+            self.pos.add_to_mesh(f);
+        }
+    }
+
+    #[test]
+    fn matching_layout() {
+        let verts = [
+            TestVertex { pos: [1.0, 2.0, 3.0], color: [4, 5, 6, 7] },
+            TestVertex { pos: [8.0, 9.0, 10.0], color: [11, 12, 13, 14] },
+        ];
+        let builder = MeshBuilder::<_, Triangles>::from_slices(&verts, &[0, 1, 1]);
+        assert_eq!(builder.vertex_data, vertex_bytes(&verts));
+        assert_eq!(builder.next_index, 2);
+        assert_eq!(builder.indices, [0, 1, 1]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn swapped_fields() {
+        check_pod_layout::<SwappedVertex>();
+    }
+
+    #[test]
+    #[should_panic]
+    fn swapped_fields_empty_slice() {
+        MeshBuilder::<SwappedVertex, Triangles>::new().verts_from_slice(&[]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn missing_attribute() {
+        check_pod_layout::<MissingAttributeVertex>();
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_out_of_range() {
+        let verts = [TestVertex { pos: [0.0; 3], color: [0; 4] }];
+        MeshBuilder::<_, Triangles>::from_slices(&verts, &[0, 1, 0]);
+    }
+}
@@ -1,12 +1,18 @@
-use cgmath::*;
 use log::*;
+use std::cell::{Ref, RefCell};
 use std::marker::PhantomData;
 use std::rc::Rc;
 use uid::*;
+use wasm_bindgen::JsValue;
 use web_sys::*;
 
 use crate::context::*;
+use crate::feedback::*;
+use crate::mesh::*;
+use crate::shader_source::*;
+use crate::std140::*;
 use crate::uniforms::*;
+use crate::vertex::*;
 
 #[doc(hidden)]
 #[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
@@ -14,12 +20,39 @@ pub(crate) struct ProgramId_(());
 
 pub(crate) type ProgramId = Id<ProgramId_>;
 
+// From the KHR_parallel_shader_compile extension.
+const COMPLETION_STATUS_KHR: u32 = 0x91B1;
+
 #[derive(Copy, Clone)]
 pub enum ShaderType {
     Vertex,
     Fragment,
 }
 
+/// How transform feedback varyings are written to the output buffers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TransformFeedbackMode {
+    /// All varyings are written to a single buffer, in the order they were listed.
+    Interleaved,
+    /// Each varying is written to its own buffer.
+    Separate,
+}
+
+impl TransformFeedbackMode {
+    fn as_gl(self) -> u32 {
+        match self {
+            TransformFeedbackMode::Interleaved => WebGl2::INTERLEAVED_ATTRIBS,
+            TransformFeedbackMode::Separate => WebGl2::SEPARATE_ATTRIBS,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct FeedbackVaryings {
+    names: Vec<String>,
+    mode: TransformFeedbackMode,
+}
+
 impl ShaderType {
     fn as_gl(self) -> u32 {
         match self {
@@ -41,201 +74,608 @@ impl<V: Vertex, U: GlUniforms> Clone for GlProgram<V, U> {
 }
 
 pub(crate) struct GlProgramInner<V: Vertex, U: GlUniforms> {
-    pub(crate) program: WebGlProgram,
-    pub(crate) gl_uniforms: U,
+    // This is in a `RefCell` so the shaders can be replaced by `reload` while the program is
+    // shared.
+    pub(crate) state: RefCell<GlProgramState<U>>,
     phantom: PhantomData<V>,
-    id: ProgramId,
+    pub(crate) id: ProgramId,
     pub(crate) context: GlContext,
-    vert_shader: WebGlShader,
-    frag_shader: WebGlShader,
+    pub(crate) feedback_varyings: Option<FeedbackVaryings>,
+    // The name, binding point, and expected size of each uniform block bound with
+    // `bind_uniform_block`, so the bindings can be restored when the program is reloaded.
+    uniform_blocks: RefCell<Vec<(String, u32, usize)>>,
+}
+
+pub(crate) struct GlProgramState<U: GlUniforms> {
+    pub(crate) objects: ProgramObjects,
+    pub(crate) gl_uniforms: U,
 }
 
 impl<V: Vertex, U: GlUniforms> Drop for GlProgramInner<V, U> {
     fn drop(&mut self) {
-        self.context.inner.delete_program(Some(&self.program));
-        self.context.inner.delete_shader(Some(&self.vert_shader));
-        self.context.inner.delete_shader(Some(&self.frag_shader));
+        self.state.borrow().objects.delete(&self.context);
     }
 }
 
-impl<V: Vertex, U: GlUniforms> GlProgram<V, U> {
-    pub fn new(context: &GlContext, vert_shader_source: &str, frag_shader_source: &str) -> Self {
-        let vert_shader = Self::load_shader(context, ShaderType::Vertex, vert_shader_source);
-        let frag_shader = Self::load_shader(context, ShaderType::Fragment, frag_shader_source);
+/// The OpenGL objects that make up a program.
+pub(crate) struct ProgramObjects {
+    pub(crate) program: WebGlProgram,
+    vert_shader: WebGlShader,
+    frag_shader: WebGlShader,
+    // The sources the shaders were compiled from, so `poll_reload` can skip unchanged sources
+    vert_shader_source: String,
+    frag_shader_source: String,
+}
+
+impl ProgramObjects {
+    /// Compiles the shaders and links the program, without waiting for the results.
+    fn submit(
+        context: &GlContext,
+        vert_shader_source: &str,
+        frag_shader_source: &str,
+        attrib_locations: &[(String, u32)],
+        feedback_varyings: Option<&FeedbackVaryings>,
+    ) -> Self {
+        let vert_shader = Self::compile_shader(context, ShaderType::Vertex, vert_shader_source);
+        let frag_shader = Self::compile_shader(context, ShaderType::Fragment, frag_shader_source);
 
         let program = context.inner.create_program().unwrap();
         context.inner.attach_shader(&program, &vert_shader);
         context.inner.attach_shader(&program, &frag_shader);
+        for (name, loc) in attrib_locations {
+            context.inner.bind_attrib_location(&program, *loc, name);
+        }
+        if let Some(feedback_varyings) = feedback_varyings {
+            let names: js_sys::Array =
+                feedback_varyings.names.iter().map(|name| JsValue::from_str(name)).collect();
+            context.inner.transform_feedback_varyings(
+                &program,
+                &names,
+                feedback_varyings.mode.as_gl(),
+            );
+        }
         context.inner.link_program(&program);
 
-        let link_status =
-            context.inner.get_program_parameter(&program, WebGl2::LINK_STATUS).as_bool().unwrap();
-        if !link_status {
-            error!(
-                "Error linking program: {}",
-                context.inner.get_program_info_log(&program).unwrap()
-            );
-            panic!();
+        ProgramObjects {
+            program,
+            vert_shader,
+            frag_shader,
+            vert_shader_source: vert_shader_source.to_string(),
+            frag_shader_source: frag_shader_source.to_string(),
+        }
+    }
+
+    fn compile_shader(context: &GlContext, shader_type: ShaderType, source: &str) -> WebGlShader {
+        let shader = context.inner.create_shader(shader_type.as_gl()).unwrap();
+        context.inner.shader_source(&shader, source);
+        context.inner.compile_shader(&shader);
+        shader
+    }
+
+    /// Like `submit`, but also waits for the results and checks them.
+    fn link(
+        context: &GlContext,
+        vert_shader_source: &str,
+        frag_shader_source: &str,
+        attrib_locations: &[(String, u32)],
+        feedback_varyings: Option<&FeedbackVaryings>,
+    ) -> Result<Self, String> {
+        let objects = Self::submit(
+            context,
+            vert_shader_source,
+            frag_shader_source,
+            attrib_locations,
+            feedback_varyings,
+        );
+        objects.check(context)?;
+        Ok(objects)
+    }
+
+    /// Returns whether the driver has finished compiling and linking the program, so that
+    /// `check` won't block. Always true if `KHR_parallel_shader_compile` isn't supported.
+    fn is_complete(&self, context: &GlContext) -> bool {
+        !context.parallel_shader_compile
+            || context
+                .inner
+                .get_program_parameter(&self.program, COMPLETION_STATUS_KHR)
+                .as_bool()
+                .unwrap_or(true)
+    }
+
+    /// Returns an error if either shader failed to compile or the program failed to link. The
+    /// objects are deleted in that case.
+    fn check(&self, context: &GlContext) -> Result<(), String> {
+        // A shader compilation error also causes a link error, but the compilation error is
+        // more useful.
+        let err = Self::shader_error(context, &self.vert_shader)
+            .or_else(|| Self::shader_error(context, &self.frag_shader))
+            .or_else(|| {
+                let link_status = context
+                    .inner
+                    .get_program_parameter(&self.program, WebGl2::LINK_STATUS)
+                    .as_bool()
+                    .unwrap();
+                if link_status {
+                    None
+                } else {
+                    Some(format!(
+                        "Error linking program: {}",
+                        context.inner.get_program_info_log(&self.program).unwrap()
+                    ))
+                }
+            });
+
+        match err {
+            Some(err) => {
+                self.delete(context);
+                Err(err)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn shader_error(context: &GlContext, shader: &WebGlShader) -> Option<String> {
+        let compile_status =
+            context.inner.get_shader_parameter(shader, WebGl2::COMPILE_STATUS).as_bool().unwrap();
+        if compile_status {
+            None
+        } else {
+            Some(format!(
+                "Error compiling shader: {}",
+                context.inner.get_shader_info_log(shader).unwrap()
+            ))
+        }
+    }
+
+    fn delete(&self, context: &GlContext) {
+        context.inner.delete_program(Some(&self.program));
+        context.inner.delete_shader(Some(&self.vert_shader));
+        context.inner.delete_shader(Some(&self.frag_shader));
+    }
+}
+
+impl<V: Vertex, U: GlUniforms> GlProgram<V, U> {
+    pub fn new(context: &GlContext, vert_shader_source: &str, frag_shader_source: &str) -> Self {
+        Self::new_impl(context, vert_shader_source, frag_shader_source, None)
+    }
+
+    /// Like `new`, but the values of the given vertex shader outputs can be captured with
+    /// `capture`. `mode` determines whether they're written interleaved into a single
+    /// `FeedbackBuffer`, or each into its own.
+    pub fn new_with_transform_feedback(
+        context: &GlContext,
+        vert_shader_source: &str,
+        frag_shader_source: &str,
+        varyings: &[&str],
+        mode: TransformFeedbackMode,
+    ) -> Self {
+        let feedback_varyings = FeedbackVaryings {
+            names: varyings.iter().map(|name| name.to_string()).collect(),
+            mode,
+        };
+        Self::new_impl(context, vert_shader_source, frag_shader_source, Some(feedback_varyings))
+    }
+
+    fn new_impl(
+        context: &GlContext,
+        vert_shader_source: &str,
+        frag_shader_source: &str,
+        feedback_varyings: Option<FeedbackVaryings>,
+    ) -> Self {
+        match ProgramObjects::link(
+            context,
+            vert_shader_source,
+            frag_shader_source,
+            &[],
+            feedback_varyings.as_ref(),
+        ) {
+            Ok(objects) => Self::from_objects(context, objects, feedback_varyings),
+            Err(err) => {
+                error!("{}", err);
+                panic!();
+            }
+        }
+    }
+
+    /// Starts compiling a program without waiting for it to finish; see `PendingProgram`.
+    ///
+    /// Unlike `new`, compilation errors are returned from `PendingProgram::poll` rather than
+    /// causing a panic.
+    pub fn new_async(
+        context: &GlContext,
+        vert_shader_source: &str,
+        frag_shader_source: &str,
+    ) -> PendingProgram<V, U> {
+        PendingProgram {
+            context: context.clone(),
+            objects: Some(ProgramObjects::submit(
+                context,
+                vert_shader_source,
+                frag_shader_source,
+                &[],
+                None,
+            )),
+            phantom: PhantomData,
         }
+    }
 
-        let gl_uniforms = U::new(context, &program);
+    fn from_objects(
+        context: &GlContext,
+        objects: ProgramObjects,
+        feedback_varyings: Option<FeedbackVaryings>,
+    ) -> Self {
+        let id = ProgramId::new();
+        let gl_uniforms = construct_uniforms(context, id, || U::new(context, &objects.program));
 
         GlProgram {
             inner: Rc::new(GlProgramInner {
-                program,
-                gl_uniforms,
+                state: RefCell::new(GlProgramState { objects, gl_uniforms }),
                 phantom: PhantomData,
-                id: ProgramId::new(),
+                id,
                 context: context.clone(),
-                vert_shader,
-                frag_shader,
+                feedback_varyings,
+                uniform_blocks: RefCell::new(vec![]),
             }),
         }
     }
 
-    fn load_shader(context: &GlContext, shader_type: ShaderType, source: &str) -> WebGlShader {
-        let shader = context.inner.create_shader(shader_type.as_gl()).unwrap();
-        context.inner.shader_source(&shader, &source);
-        context.inner.compile_shader(&shader);
+    /// Replaces the program's shaders with ones compiled from the given sources. Every clone of
+    /// this `GlProgram` (including the ones held by meshes) uses the new shaders from then on.
+    ///
+    /// The uniform locations are looked up again with `GlUniforms::try_new`, and each attribute
+    /// keeps the location it had in the old program so existing meshes don't need to be rebuilt.
+    ///
+    /// If the new shaders fail to compile or link, or `GlUniforms::try_new` returns an error for
+    /// the new program, the error is logged and returned, and the previous shaders stay active.
+    /// `try_new` only returns an error for a missing uniform if it's implemented to; see its
+    /// documentation.
+    pub fn reload(&self, vert_shader_source: &str, frag_shader_source: &str) -> Result<(), String> {
+        let context = &self.inner.context;
+        let attrib_locations =
+            active_attrib_locations(context, &self.inner.state.borrow().objects.program);
+
+        let objects = match ProgramObjects::link(
+            context,
+            vert_shader_source,
+            frag_shader_source,
+            &attrib_locations,
+            self.inner.feedback_varyings.as_ref(),
+        ) {
+            Ok(objects) => objects,
+            Err(err) => {
+                error!("Keeping the previous program: {}", err);
+                return Err(err);
+            }
+        };
+
+        for (block_name, binding, size) in self.inner.uniform_blocks.borrow().iter() {
+            if let Err(err) =
+                bind_uniform_block(context, &objects.program, block_name, *binding, *size)
+            {
+                objects.delete(context);
+                error!("Keeping the previous program: {}", err);
+                return Err(err);
+            }
+        }
 
-        let compile_status =
-            context.inner.get_shader_parameter(&shader, WebGl2::COMPILE_STATUS).as_bool().unwrap();
-        if !compile_status {
-            error!(
-                "Error compiling shader: {}",
-                context.inner.get_shader_info_log(&shader).unwrap()
-            );
+        let gl_uniforms = match construct_uniforms(context, self.inner.id, || {
+            U::try_new(context, &objects.program)
+        }) {
+            Ok(gl_uniforms) => gl_uniforms,
+            Err(err) => {
+                objects.delete(context);
+                error!("Keeping the previous program: {}", err);
+                return Err(err);
+            }
+        };
+
+        let mut state = self.inner.state.borrow_mut();
+        state.objects.delete(context);
+        *state = GlProgramState { objects, gl_uniforms };
+
+        // The new program has to be passed to `useProgram` the next time it's bound.
+        let mut cache = context.cache.borrow_mut();
+        if cache.bound_program == Some(self.inner.id) {
+            cache.bound_program = None;
+        }
+
+        Ok(())
+    }
+
+    /// Reloads the program if the `ShaderSourceProvider` has new sources. Sources that are the
+    /// same as the ones the program was last compiled from are ignored, so the first poll of a
+    /// provider doesn't relink the program.
+    ///
+    /// Returns `Ok(true)` if the program was reloaded, `Ok(false)` if the sources haven't
+    /// changed, and an error if the new sources failed to compile; see `reload` for details.
+    pub fn poll_reload(&self, provider: &mut impl ShaderSourceProvider) -> Result<bool, String> {
+        match provider.poll() {
+            Some((vert_shader_source, frag_shader_source)) => {
+                let unchanged = {
+                    let objects = &self.inner.state.borrow().objects;
+                    objects.vert_shader_source == vert_shader_source
+                        && objects.frag_shader_source == frag_shader_source
+                };
+                if unchanged {
+                    return Ok(false);
+                }
+                self.reload(&vert_shader_source, &frag_shader_source).map(|()| true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Makes the program read the uniform block with the given name from the `UniformBuffer`
+    /// bound to `binding`.
+    ///
+    /// Panics if the program doesn't contain the block, or if the block's size doesn't match the
+    /// std140 layout of `T`.
+    pub fn bind_uniform_block<T: Std140>(&self, block_name: &str, binding: u32) {
+        let context = &self.inner.context;
+        if let Err(err) = bind_uniform_block(
+            context,
+            &self.inner.state.borrow().objects.program,
+            block_name,
+            binding,
+            T::SIZE,
+        ) {
+            error!("{}", err);
             panic!();
         }
 
-        shader
+        let mut uniform_blocks = self.inner.uniform_blocks.borrow_mut();
+        uniform_blocks.retain(|(name, _, _)| name != block_name);
+        uniform_blocks.push((block_name.to_string(), binding, T::SIZE));
     }
 
-    pub(crate) fn bind(&self, context: &GlContext) {
+    /// Runs the vertex shader on each vertex in `input` and writes the transform feedback
+    /// varyings to `outputs`, without rasterizing anything. The program must have been created
+    /// with `new_with_transform_feedback`.
+    ///
+    /// In `TransformFeedbackMode::Interleaved`, there must be exactly one output; otherwise there
+    /// must be one for each varying, in the same order. Each output must have room for all of
+    /// the input's vertices, and can't be the same buffer as the input; otherwise this panics.
+    pub fn capture<P: FeedbackPrimitive>(
+        &self,
+        input: &FeedbackBuffer<V, P>,
+        uniforms: &impl Uniforms<GlUniforms = U>,
+        outputs: &[&dyn FeedbackTarget],
+    ) {
+        let context = &self.inner.context;
+        let feedback_varyings = self
+            .inner
+            .feedback_varyings
+            .as_ref()
+            .expect("The program wasn't created with transform feedback varyings");
+        match feedback_varyings.mode {
+            TransformFeedbackMode::Interleaved => assert_eq!(outputs.len(), 1),
+            TransformFeedbackMode::Separate => {
+                assert_eq!(outputs.len(), feedback_varyings.names.len())
+            }
+        }
+
+        let num_vertices = input.len();
+        if num_vertices == 0 {
+            return;
+        }
+        for output in outputs {
+            assert!(output.capacity() >= num_vertices, "Transform feedback output is too small");
+            // Each `FeedbackBuffer` owns its GL buffer, so they're the same buffer only if
+            // they're the same object
+            let output_ptr = *output as *const dyn FeedbackTarget as *const u8;
+            if std::ptr::eq(output_ptr, input as *const FeedbackBuffer<V, P> as *const u8) {
+                error!("The input of a transform feedback capture can't also be an output");
+                panic!();
+            }
+        }
+
+        input.bind(self);
+        let bound_program = self.bind(context);
+        uniforms.update(&bound_program);
+
+        for (i, output) in outputs.iter().enumerate() {
+            context.inner.bind_buffer_base(
+                WebGl2::TRANSFORM_FEEDBACK_BUFFER,
+                i as u32,
+                Some(output.buffer()),
+            );
+        }
+        context.enable(GlFlag::RasterizerDiscard);
+        context.inner.begin_transform_feedback(P::AS_GL);
+        context.inner.draw_arrays(P::AS_GL, 0, num_vertices as i32);
+        context.inner.end_transform_feedback();
+        context.disable(GlFlag::RasterizerDiscard);
+
+        // The outputs can't be used as vertex data while they're still bound for transform
+        // feedback.
+        for i in 0..outputs.len() {
+            context.inner.bind_buffer_base(WebGl2::TRANSFORM_FEEDBACK_BUFFER, i as u32, None);
+        }
+        context.inner.bind_buffer(WebGl2::TRANSFORM_FEEDBACK_BUFFER, None);
+
+        for output in outputs {
+            output.set_len(num_vertices);
+        }
+    }
+
+    /// Binds the program in preparation for a draw, which also makes every texture unit
+    /// available for the draw's samplers. The returned `BoundProgram` is what allows the
+    /// program's uniforms to be set.
+    pub(crate) fn bind<'a>(&'a self, context: &'a GlContext) -> BoundProgram<'a, U> {
         let mut cache = context.cache.borrow_mut();
+        cache.reset_texture_units();
+        let state = self.inner.state.borrow();
         if cache.bound_program != Some(self.inner.id) {
             cache.bound_program = Some(self.inner.id);
-            context.inner.use_program(Some(&self.inner.program));
+            context.inner.use_program(Some(&state.objects.program));
         }
+        BoundProgram { context, state, id: self.inner.id }
     }
 }
 
-/// A list of all OpenGL attributes for a given program.
-///
-/// Each pair is (attribute name, attribute size).
-///
-/// The size should be the size in *floats*, not bytes.
-pub type Attributes = &'static [(&'static str, i32)];
-
-/// A vertex for a given program.
-///
-/// Example implementation:
-/// ```
-/// struct ExampleVertex {
-///     pos: Vector2<f32>,
-///     uv: Vector2<f32>,
-/// }
-///
-/// impl VertexData for ExampleVertex {
-///     const ATTRIBUTES: Attributes = &[("pos", 2), ("uv", 2)];
-/// }
+/// Proof that a program is currently bound, passed to `Uniforms::update`.
 ///
-/// impl VertexComponent for ExampleVertex {
-///     fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-///         self.pos.add_to_mesh(f);
-///         self.uv.add_to_mesh(f);
-///     }
-/// }
-/// ```
-pub trait Vertex: VertexData + VertexComponent {}
+/// Every uniform's `set` method requires one, and it can only be created by the draw calls in
+/// this crate, so uniforms can't be set while some other program is bound. Setting a uniform
+/// that was looked up in a different program panics.
+pub struct BoundProgram<'a, U: GlUniforms> {
+    context: &'a GlContext,
+    state: Ref<'a, GlProgramState<U>>,
+    id: ProgramId,
+}
 
-impl<T: VertexData + VertexComponent> Vertex for T {}
+impl<'a, U: GlUniforms> BoundProgram<'a, U> {
+    /// Returns the uniform locations of the bound program.
+    pub fn uniforms(&self) -> &U {
+        &self.state.gl_uniforms
+    }
 
-pub trait VertexData {
-    /// A list of all OpenGL attributes that each vertex contains.
-    const ATTRIBUTES: Attributes;
+    pub fn context(&self) -> &GlContext {
+        self.context
+    }
 
-    // TODO: find a way to cache this
-    fn stride() -> i32 {
-        Self::ATTRIBUTES.iter().map(|&(_, size)| size).sum()
+    pub(crate) fn id(&self) -> ProgramId {
+        self.id
     }
 }
 
-/// A component of a vertex.
+/// Shader sources that are embedded in the binary, along with the vertex type they expect.
 ///
-/// See the `Vertex` trait for an example implementation.
-pub trait VertexComponent {
-    /// Adds the `VertexComponent` to a mesh by calling the given closure for each
-    /// `f32` component, in order. Composite `VertexComponent` instances can call
-    /// `add_to_mesh` for each of their components rather than calling the closure directly.
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32));
+/// This is implemented on a `GlUniforms` type by the code that `generate_bindings` generates,
+/// so that `GlProgram::new_embedded` can only create a program with matching vertex and uniform
+/// types.
+pub trait EmbeddedShaders: GlUniforms {
+    type Vertex: Vertex;
+    const VERT_SHADER: &'static str;
+    const FRAG_SHADER: &'static str;
 }
 
-impl VertexComponent for f32 {
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-        f(*self);
+impl<U: EmbeddedShaders> GlProgram<U::Vertex, U> {
+    /// Creates a program from the shaders embedded by `U`.
+    pub fn new_embedded(context: &GlContext) -> Self {
+        Self::new(context, U::VERT_SHADER, U::FRAG_SHADER)
     }
 }
 
-impl VertexComponent for Vector2<f32> {
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-        f(self.x);
-        f(self.y);
-    }
+/// A program that's still being compiled; created by `GlProgram::new_async`.
+///
+/// When the `KHR_parallel_shader_compile` extension is available, the driver compiles the
+/// program in the background and `poll` returns `None` until it's done. Otherwise, checking the
+/// results is deferred until the first call to `poll`.
+///
+/// Example usage:
+/// ```
+/// let mut pending = GlProgram::<SpriteVertex, SpriteUniformsGl>::new_async(
+///     &context,
+///     SPRITE_VERT_SHADER,
+///     SPRITE_FRAG_SHADER,
+/// );
+///
+/// // Once per frame, while drawing the loading screen:
+/// if let Some(program) = pending.poll() {
+///     let program = program.unwrap();
+///     // ...
+/// }
+/// ```
+pub struct PendingProgram<V: Vertex, U: GlUniforms> {
+    context: GlContext,
+    // This is `None` once the result has been returned from `poll`.
+    objects: Option<ProgramObjects>,
+    phantom: PhantomData<(V, U)>,
 }
 
-impl VertexComponent for Vector3<f32> {
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-        f(self.x);
-        f(self.y);
-        f(self.z);
+impl<V: Vertex, U: GlUniforms> PendingProgram<V, U> {
+    /// Returns true if the program has finished compiling, so that `poll` will return it.
+    pub fn is_ready(&self) -> bool {
+        match &self.objects {
+            Some(objects) => objects.is_complete(&self.context),
+            None => false,
+        }
     }
-}
 
-impl VertexComponent for Vector4<f32> {
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-        f(self.x);
-        f(self.y);
-        f(self.z);
-        f(self.w);
+    /// Returns the program, or an error if it failed to compile, once compilation has finished.
+    /// Returns `None` while it's still compiling.
+    ///
+    /// Panics if the result has already been returned by a previous call.
+    pub fn poll(&mut self) -> Option<Result<GlProgram<V, U>, String>> {
+        let objects = self.objects.as_ref().expect("PendingProgram polled after completion");
+        if !objects.is_complete(&self.context) {
+            return None;
+        }
+        Some(self.finish())
     }
-}
 
-impl VertexComponent for Point2<f32> {
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-        f(self.x);
-        f(self.y);
+    /// Waits for the program to finish compiling and returns it.
+    ///
+    /// Panics if the result has already been returned by `poll`.
+    pub fn finish(&mut self) -> Result<GlProgram<V, U>, String> {
+        let objects = self.objects.take().expect("PendingProgram polled after completion");
+        objects.check(&self.context)?;
+        Ok(GlProgram::from_objects(&self.context, objects, None))
     }
 }
 
-impl VertexComponent for Point3<f32> {
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-        f(self.x);
-        f(self.y);
-        f(self.z);
+impl<V: Vertex, U: GlUniforms> Drop for PendingProgram<V, U> {
+    fn drop(&mut self) {
+        if let Some(objects) = &self.objects {
+            objects.delete(&self.context);
+        }
     }
 }
 
-impl VertexComponent for [f32; 2] {
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-        f(self[0]);
-        f(self[1]);
+fn bind_uniform_block(
+    context: &GlContext,
+    program: &WebGlProgram,
+    block_name: &str,
+    binding: u32,
+    size: usize,
+) -> Result<(), String> {
+    let index = context.inner.get_uniform_block_index(program, block_name);
+    if index == WebGl2::INVALID_INDEX {
+        return Err(format!("Uniform block not found: {}", block_name));
     }
-}
 
-impl VertexComponent for [f32; 3] {
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-        f(self[0]);
-        f(self[1]);
-        f(self[2]);
+    // Some drivers don't include the padding at the end of the block in its size, but the std140
+    // size always does.
+    let block_size = context
+        .inner
+        .get_active_uniform_block_parameter(program, index, WebGl2::UNIFORM_BLOCK_DATA_SIZE)
+        .unwrap()
+        .as_f64()
+        .unwrap() as usize;
+    if std140_align_to(block_size, 16) != size {
+        return Err(format!(
+            "Uniform block {} has size {} in the program, but its std140 layout has size {}",
+            block_name, block_size, size
+        ));
     }
+
+    context.inner.uniform_block_binding(program, index, binding);
+    Ok(())
 }
 
-impl VertexComponent for [f32; 4] {
-    fn add_to_mesh(&self, f: &mut dyn FnMut(f32)) {
-        f(self[0]);
-        f(self[1]);
-        f(self[2]);
-        f(self[3]);
+/// Calls `f`, which creates the `GlUniforms` of the program with the given id. The uniforms
+/// record the id, so that `set` can check that they belong to the bound program.
+fn construct_uniforms<T>(context: &GlContext, id: ProgramId, f: impl FnOnce() -> T) -> T {
+    context.cache.borrow_mut().constructing_program = Some(id);
+    let res = f();
+    context.cache.borrow_mut().constructing_program = None;
+    res
+}
+
+/// Returns the name and location of each active attribute in the program.
+fn active_attrib_locations(context: &GlContext, program: &WebGlProgram) -> Vec<(String, u32)> {
+    let num_attribs =
+        context.inner.get_program_parameter(program, WebGl2::ACTIVE_ATTRIBUTES).as_f64().unwrap()
+            as u32;
+    let mut res = Vec::with_capacity(num_attribs as usize);
+    for i in 0..num_attribs {
+        if let Some(info) = context.inner.get_active_attrib(program, i) {
+            let loc = context.inner.get_attrib_location(program, &info.name());
+            if loc >= 0 {
+                res.push((info.name(), loc as u32));
+            }
+        }
     }
+    res
 }
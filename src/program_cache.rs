@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use crate::context::*;
+use crate::program::*;
+use crate::uniforms::*;
+use crate::vertex::*;
+
+/// Statistics about the variants compiled by a `ProgramCache`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ProgramCacheStats {
+    /// The number of distinct variants that have been compiled.
+    pub num_variants: usize,
+    /// The number of requests that were served by an already-compiled variant.
+    pub num_hits: usize,
+    /// The total time spent compiling and linking variants, in milliseconds.
+    pub total_compile_time_ms: f64,
+    /// The longest time spent compiling and linking a single variant, in milliseconds.
+    pub max_compile_time_ms: f64,
+}
+
+/// Compiles variants of a program on demand, one for each distinct set of enabled defines.
+///
+/// Each define is inserted as `#define <define>` right after the `#version` line of both shaders,
+/// so a define can also be given a value, e.g. `"NUM_LIGHTS 4"`. The order of the defines and
+/// any duplicates don't matter; requesting the same set twice returns a clone of the same
+/// `GlProgram`.
+///
+/// Example usage:
+/// ```
+/// let mut cache = ProgramCache::<MaterialVertex, MaterialUniformsGl>::new(
+///     &context,
+///     MATERIAL_VERT_SHADER,
+///     MATERIAL_FRAG_SHADER,
+/// );
+/// let program = cache.get(&["NORMAL_MAP", "FOG"]);
+/// ```
+pub struct ProgramCache<V: Vertex, U: GlUniforms> {
+    context: GlContext,
+    vert_shader_source: String,
+    frag_shader_source: String,
+    variants: HashMap<Vec<String>, GlProgram<V, U>>,
+    stats: ProgramCacheStats,
+}
+
+impl<V: Vertex, U: GlUniforms> ProgramCache<V, U> {
+    /// Creates an empty `ProgramCache`. No programs are compiled until they're requested.
+    pub fn new(context: &GlContext, vert_shader_source: &str, frag_shader_source: &str) -> Self {
+        ProgramCache {
+            context: context.clone(),
+            vert_shader_source: vert_shader_source.to_string(),
+            frag_shader_source: frag_shader_source.to_string(),
+            variants: HashMap::new(),
+            stats: ProgramCacheStats::default(),
+        }
+    }
+
+    /// Returns the variant with the given defines enabled, compiling it if it hasn't been
+    /// requested before.
+    pub fn get(&mut self, defines: &[&str]) -> GlProgram<V, U> {
+        let mut key: Vec<String> = defines.iter().map(|define| define.trim().to_string()).collect();
+        key.sort();
+        key.dedup();
+
+        if let Some(program) = self.variants.get(&key) {
+            self.stats.num_hits += 1;
+            return program.clone();
+        }
+
+        let vert_shader_source = insert_defines(&self.vert_shader_source, &key);
+        let frag_shader_source = insert_defines(&self.frag_shader_source, &key);
+
+        let start_time = js_sys::Date::now();
+        let program = GlProgram::new(&self.context, &vert_shader_source, &frag_shader_source);
+        let compile_time = js_sys::Date::now() - start_time;
+
+        self.stats.num_variants += 1;
+        self.stats.total_compile_time_ms += compile_time;
+        self.stats.max_compile_time_ms = self.stats.max_compile_time_ms.max(compile_time);
+
+        self.variants.insert(key, program.clone());
+        program
+    }
+
+    /// Returns statistics about the variants compiled so far.
+    pub fn stats(&self) -> ProgramCacheStats {
+        self.stats
+    }
+
+    /// Removes all variants from the cache. Programs that are still in use elsewhere (e.g. by
+    /// a `Mesh`) stay alive until they're dropped.
+    pub fn clear(&mut self) {
+        self.variants.clear();
+        self.stats = ProgramCacheStats::default();
+    }
+}
+
+/// Inserts a `#define` for each define into the source. They have to go after the `#version`
+/// line, since that must come before anything else in the shader. A `#line` directive after them
+/// keeps the line numbers in compile errors the same as in the original source.
+fn insert_defines(source: &str, defines: &[String]) -> String {
+    let mut header = String::new();
+    for define in defines {
+        header.push_str("#define ");
+        header.push_str(define);
+        header.push('\n');
+    }
+
+    let version_start = source.len() - source.trim_start().len();
+    if source[version_start..].starts_with("#version") {
+        let version_end =
+            source[version_start..].find('\n').map_or(source.len(), |i| version_start + i + 1);
+        let mut res = source[..version_end].to_string();
+        if !res.ends_with('\n') {
+            res.push('\n');
+        }
+        res.push_str(&header);
+        // The line after the `#version` line
+        let next_line = source[..version_end].trim_end_matches('\n').matches('\n').count() + 2;
+        res.push_str(&format!("#line {}\n", next_line));
+        res.push_str(&source[version_end..]);
+        res
+    } else {
+        header + "#line 1\n" + source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defines_after_version() {
+        let source = "#version 300 es\nprecision mediump float;\n";
+        assert_eq!(
+            insert_defines(source, &["A".to_string(), "B 2".to_string()]),
+            "#version 300 es\n#define A\n#define B 2\n#line 2\nprecision mediump float;\n"
+        );
+    }
+
+    #[test]
+    fn line_numbers_after_leading_blank_lines() {
+        let source = "\n\n#version 300 es\nvoid main() {}";
+        assert_eq!(
+            insert_defines(source, &["A".to_string()]),
+            "\n\n#version 300 es\n#define A\n#line 4\nvoid main() {}"
+        );
+    }
+
+    #[test]
+    fn version_without_newline() {
+        assert_eq!(
+            insert_defines("#version 300 es", &["A".to_string()]),
+            "#version 300 es\n#define A\n#line 2\n"
+        );
+    }
+
+    #[test]
+    fn no_version() {
+        assert_eq!(
+            insert_defines("void main() {}", &["A".to_string()]),
+            "#define A\n#line 1\nvoid main() {}"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use log::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::*;
+
+/// A source of shader code that can change while the app is running, for use with
+/// `GlProgram::poll_reload`.
+///
+/// `FetchShaderSource` polls a web server for changes. Other providers, such as one that's
+/// notified by a file watcher in the dev server, can be written by implementing this trait.
+pub trait ShaderSourceProvider {
+    /// Returns the vertex and fragment shader sources if either has changed since the last
+    /// call, or `None` otherwise. The first sources a provider returns may be the same as the
+    /// ones the program was built with; `GlProgram::poll_reload` ignores them in that case.
+    fn poll(&mut self) -> Option<(String, String)>;
+}
+
+// The vertex and fragment shader results of a request; the inner `Option` is `None` if the
+// request failed.
+type FetchResults = Rc<RefCell<[Option<Option<String>>; 2]>>;
+
+/// A `ShaderSourceProvider` that periodically fetches the shaders from a pair of URLs.
+///
+/// This is meant for development, while the shaders are served by a local web server.
+///
+/// Example usage:
+/// ```
+/// let mut source = FetchShaderSource::new("shaders/sprite.vert", "shaders/sprite.frag", 500.0);
+///
+/// // Once per frame:
+/// let _ = program.poll_reload(&mut source);
+/// ```
+pub struct FetchShaderSource {
+    vert_url: String,
+    frag_url: String,
+    poll_interval_ms: f64,
+    last_poll_time: Option<f64>,
+    pending: Option<FetchResults>,
+    current: Option<(String, String)>,
+}
+
+impl FetchShaderSource {
+    /// Creates a `FetchShaderSource` that requests the shaders at most once per
+    /// `poll_interval_ms` milliseconds.
+    pub fn new(vert_url: &str, frag_url: &str, poll_interval_ms: f64) -> Self {
+        FetchShaderSource {
+            vert_url: vert_url.to_string(),
+            frag_url: frag_url.to_string(),
+            poll_interval_ms,
+            last_poll_time: None,
+            pending: None,
+            current: None,
+        }
+    }
+
+    fn start_request(&mut self) {
+        let results = Rc::new(RefCell::new([None, None]));
+        fetch_text(&self.vert_url, results.clone(), 0);
+        fetch_text(&self.frag_url, results.clone(), 1);
+        self.pending = Some(results);
+    }
+}
+
+impl ShaderSourceProvider for FetchShaderSource {
+    fn poll(&mut self) -> Option<(String, String)> {
+        if let Some(pending) = self.pending.take() {
+            let results = match &mut *pending.borrow_mut() {
+                [Some(vert), Some(frag)] => Some((vert.take(), frag.take())),
+                _ => None,
+            };
+            match results {
+                Some((Some(vert), Some(frag))) => {
+                    let sources = (vert, frag);
+                    if self.current.as_ref() != Some(&sources) {
+                        self.current = Some(sources.clone());
+                        return Some(sources);
+                    }
+                }
+                // The failure has already been logged; try again after the next interval.
+                Some(_) => {}
+                None => self.pending = Some(pending),
+            }
+            return None;
+        }
+
+        let now = js_sys::Date::now();
+        let poll_due = match self.last_poll_time {
+            Some(last_poll_time) => now - last_poll_time >= self.poll_interval_ms,
+            None => true,
+        };
+        if poll_due {
+            self.last_poll_time = Some(now);
+            self.start_request();
+        }
+        None
+    }
+}
+
+/// Requests the URL's contents and stores them in `results[index]` once they arrive.
+fn fetch_text(url: &str, results: FetchResults, index: usize) {
+    let request = XmlHttpRequest::new().unwrap();
+    // A query string with the current time prevents the browser from returning a cached copy.
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let url_with_time = format!("{}{}t={}", url, separator, js_sys::Date::now());
+    request.open_with_async("GET", &url_with_time, true).unwrap();
+
+    let url = url.to_string();
+    let request2 = request.clone();
+    let on_load_end = wasm_bindgen::closure::Closure::once_into_js(move |_: JsValue| {
+        let status = request2.status().unwrap_or(0);
+        let text = if (200..300).contains(&status) {
+            request2.response_text().ok().and_then(|text| text)
+        } else {
+            None
+        };
+        if text.is_none() {
+            warn!("Unable to fetch shader source from {} (status {})", url, status);
+        }
+        results.borrow_mut()[index] = Some(text);
+    });
+    request.set_onloadend(Some(on_load_end.unchecked_ref()));
+    request.send().unwrap();
+}
@@ -0,0 +1,233 @@
+use cgmath::*;
+
+/// A type with a known std140 layout, so it can be stored in a `UniformBuffer`.
+///
+/// This is implemented for scalars, `cgmath` vectors and square matrices, and arrays. Note
+/// that arrays use the std140 array layout, where each element is aligned to 16 bytes, so use a
+/// `Vector4<f32>` rather than a `[f32; 4]` for a `vec4`.
+///
+/// Structs should implement this with `#[derive(Std140)]`, which computes the offset of each
+/// field and the padding between them. Every field's type must implement `Std140`.
+///
+/// Example usage:
+/// ```
+/// #[derive(Std140)]
+/// pub struct CameraBlock {
+///     pub view_proj: Matrix4<f32>,
+///     pub eye_pos: Vector3<f32>,
+///     pub time: f32,
+/// }
+/// ```
+pub trait Std140 {
+    /// The base alignment of the type, in bytes.
+    const ALIGN: usize;
+
+    /// The size of the type in bytes. For structs and arrays this includes the padding at the
+    /// end.
+    const SIZE: usize;
+
+    /// Appends the value to `out`, which contains the block's data up to this value. Padding
+    /// is added first, so that the value is correctly aligned.
+    fn write_std140(&self, out: &mut Vec<u8>);
+}
+
+/// Rounds `offset` up to a multiple of `align`.
+#[doc(hidden)]
+pub const fn std140_align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// Pads `out` with zeros until its length is a multiple of `align`.
+#[doc(hidden)]
+pub fn std140_pad(out: &mut Vec<u8>, align: usize) {
+    out.resize(std140_align_to(out.len(), align), 0);
+}
+
+macro_rules! impl_std140_scalar {
+    ($ty:ty, $to_bytes:expr) => {
+        impl Std140 for $ty {
+            const ALIGN: usize = 4;
+            const SIZE: usize = 4;
+
+            fn write_std140(&self, out: &mut Vec<u8>) {
+                std140_pad(out, 4);
+                let to_bytes: fn(&$ty) -> [u8; 4] = $to_bytes;
+                out.extend_from_slice(&to_bytes(self));
+            }
+        }
+    };
+}
+
+impl_std140_scalar!(f32, |x| x.to_le_bytes());
+impl_std140_scalar!(i32, |x| x.to_le_bytes());
+impl_std140_scalar!(u32, |x| x.to_le_bytes());
+impl_std140_scalar!(bool, |x| (*x as u32).to_le_bytes());
+
+macro_rules! impl_std140_vector {
+    ($ty:ident, $scalar:ty, $align:expr, $($field:ident),*) => {
+        impl Std140 for $ty<$scalar> {
+            const ALIGN: usize = $align;
+            const SIZE: usize = 4 * [$(stringify!($field)),*].len();
+
+            fn write_std140(&self, out: &mut Vec<u8>) {
+                std140_pad(out, Self::ALIGN);
+                $(self.$field.write_std140(out);)*
+            }
+        }
+    };
+}
+
+macro_rules! impl_std140_vectors {
+    ($scalar:ty) => {
+        impl_std140_vector!(Vector2, $scalar, 8, x, y);
+        impl_std140_vector!(Vector3, $scalar, 16, x, y, z);
+        impl_std140_vector!(Vector4, $scalar, 16, x, y, z, w);
+        impl_std140_vector!(Point2, $scalar, 8, x, y);
+        impl_std140_vector!(Point3, $scalar, 16, x, y, z);
+    };
+}
+
+impl_std140_vectors!(f32);
+impl_std140_vectors!(i32);
+impl_std140_vectors!(u32);
+impl_std140_vectors!(bool);
+
+macro_rules! impl_std140_matrix {
+    ($ty:ident, $($col:ident),*) => {
+        // Matrices are stored like arrays of column vectors, so each column is aligned to 16
+        // bytes.
+        impl Std140 for $ty<f32> {
+            const ALIGN: usize = 16;
+            const SIZE: usize = 16 * [$(stringify!($col)),*].len();
+
+            fn write_std140(&self, out: &mut Vec<u8>) {
+                $(
+                    std140_pad(out, 16);
+                    self.$col.write_std140(out);
+                )*
+                std140_pad(out, 16);
+            }
+        }
+    };
+}
+
+impl_std140_matrix!(Matrix2, x, y);
+impl_std140_matrix!(Matrix3, x, y, z);
+impl_std140_matrix!(Matrix4, x, y, z, w);
+
+impl<T: Std140, const N: usize> Std140 for [T; N] {
+    const ALIGN: usize = std140_align_to(T::ALIGN, 16);
+    const SIZE: usize = N * std140_align_to(T::SIZE, Self::ALIGN);
+
+    fn write_std140(&self, out: &mut Vec<u8>) {
+        let stride = std140_align_to(T::SIZE, Self::ALIGN);
+        std140_pad(out, Self::ALIGN);
+        let start = out.len();
+        for (i, elem) in self.iter().enumerate() {
+            out.resize(start + i * stride, 0);
+            elem.write_std140(out);
+        }
+        out.resize(start + Self::SIZE, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Std140;
+
+    fn to_std140<T: Std140>(value: &T) -> Vec<u8> {
+        let mut out = vec![];
+        value.write_std140(&mut out);
+        out
+    }
+
+    fn f32s(bytes: &[u8]) -> Vec<f32> {
+        bytes.chunks(4).map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]])).collect()
+    }
+
+    #[derive(Std140)]
+    struct Mixed {
+        a: f32,
+        b: Vector3<f32>,
+        c: f32,
+    }
+
+    #[derive(Std140)]
+    struct Small {
+        a: Vector2<f32>,
+    }
+
+    #[derive(Std140)]
+    struct Nested {
+        a: f32,
+        b: Small,
+        c: [Small; 2],
+        d: f32,
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(to_std140(&1.5f32), 1.5f32.to_le_bytes());
+        assert_eq!(to_std140(&-2i32), (-2i32).to_le_bytes());
+        assert_eq!(to_std140(&true), 1u32.to_le_bytes());
+    }
+
+    #[test]
+    fn vectors_are_aligned() {
+        let mut out = vec![];
+        1.0f32.write_std140(&mut out);
+        Vector2::new(2.0f32, 3.0).write_std140(&mut out);
+        assert_eq!(f32s(&out), [1.0, 0.0, 2.0, 3.0]);
+
+        let mut out = vec![];
+        1.0f32.write_std140(&mut out);
+        Vector3::new(2.0f32, 3.0, 4.0).write_std140(&mut out);
+        // A vec3 is aligned like a vec4, but a scalar can follow it in the last 4 bytes
+        5.0f32.write_std140(&mut out);
+        assert_eq!(f32s(&out), [1.0, 0.0, 0.0, 0.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn array_elements_have_a_stride_of_16() {
+        assert_eq!(<[f32; 3]>::ALIGN, 16);
+        assert_eq!(<[f32; 3]>::SIZE, 48);
+        let out = to_std140(&[1.0f32, 2.0, 3.0]);
+        assert_eq!(out.len(), 48);
+        assert_eq!(f32s(&out[0..4]), [1.0]);
+        assert_eq!(f32s(&out[16..20]), [2.0]);
+        assert_eq!(f32s(&out[32..36]), [3.0]);
+    }
+
+    #[test]
+    fn matrix_columns_are_padded() {
+        assert_eq!(Matrix3::<f32>::SIZE, 48);
+        let out = to_std140(&Matrix3::new(1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0));
+        assert_eq!(f32s(&out), [1.0, 2.0, 3.0, 0.0, 4.0, 5.0, 6.0, 0.0, 7.0, 8.0, 9.0, 0.0]);
+        assert_eq!(Matrix2::<f32>::SIZE, 32);
+        assert_eq!(to_std140(&Matrix2::new(1.0f32, 2.0, 3.0, 4.0)).len(), 32);
+    }
+
+    #[test]
+    fn derived_struct_layout() {
+        assert_eq!(Mixed::ALIGN, 16);
+        assert_eq!(Mixed::SIZE, 32);
+        let out = to_std140(&Mixed { a: 1.0, b: Vector3::new(2.0, 3.0, 4.0), c: 5.0 });
+        assert_eq!(f32s(&out), [1.0, 0.0, 0.0, 0.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn nested_structs_are_aligned_to_16() {
+        assert_eq!(Small::ALIGN, 16);
+        assert_eq!(Small::SIZE, 16);
+        // a: 0, b: 16, c: 32 and 48, d: 64, padded to 80
+        assert_eq!(Nested::SIZE, 80);
+        let small = |x| Small { a: Vector2::new(x, x) };
+        let out = to_std140(&Nested { a: 1.0, b: small(2.0), c: [small(3.0), small(4.0)], d: 5.0 });
+        assert_eq!(out.len(), 80);
+        assert_eq!(f32s(&out[16..24]), [2.0, 2.0]);
+        assert_eq!(f32s(&out[32..40]), [3.0, 3.0]);
+        assert_eq!(f32s(&out[48..56]), [4.0, 4.0]);
+        assert_eq!(f32s(&out[64..68]), [5.0]);
+    }
+}
@@ -0,0 +1,125 @@
+use std::marker::PhantomData;
+use web_sys::*;
+
+use crate::buffer::*;
+use crate::context::*;
+use crate::mesh::*;
+use crate::program::*;
+use crate::surface::*;
+use crate::uniforms::*;
+use crate::vertex::*;
+
+/// A mesh for geometry that changes every frame, such as debug lines and immediate-mode UI.
+///
+/// Rebuilding a `Mesh` every frame reallocates its buffers each time. A `StreamingMesh` instead
+/// writes each `MeshBuilder` that's drawn into the unused part of a large ring buffer, and draws
+/// it from there by offsetting the vertex attributes and indices. When the ring buffer is full,
+/// it's orphaned: its storage is re-specified, so the driver can hand out new memory rather
+/// than waiting for the GPU to finish with the old contents.
+///
+/// Example usage:
+/// ```
+/// let mut debug_lines = StreamingMesh::new(&context, &program, DrawMode::Draw2D, 65536, 65536);
+/// let mut builder = MeshBuilder::new();
+/// // Every frame:
+/// builder.clear();
+/// let a = builder.vert(ColorVertex { pos: start, color });
+/// let b = builder.vert(ColorVertex { pos: end, color });
+/// builder.line(a, b);
+/// debug_lines.draw(&surface, &uniforms, &builder);
+/// ```
+pub struct StreamingMesh<V: Vertex, U: GlUniforms, P: Primitive> {
+    vao: WebGlVertexArrayObject,
+    // Ring buffers; each one's length is the end of its used part
+    vbo: GlBuffer,
+    ibo: GlBuffer,
+    context: GlContext,
+    program: GlProgram<V, U>,
+    phantom: PhantomData<P>,
+    draw_mode: DrawMode,
+}
+
+impl<V: Vertex, U: GlUniforms, P: Primitive> Drop for StreamingMesh<V, U, P> {
+    fn drop(&mut self) {
+        self.context.inner.delete_vertex_array(Some(&self.vao));
+    }
+}
+
+impl<V: Vertex, U: GlUniforms, P: Primitive> StreamingMesh<V, U, P> {
+    /// Creates a `StreamingMesh` whose ring buffers have room for `max_vertices` vertices and
+    /// `max_indices` 32-bit indices. These should be large enough for several frames of
+    /// geometry; if a single `MeshBuilder` doesn't fit, the buffers are enlarged.
+    pub fn new(
+        context: &GlContext,
+        program: &GlProgram<V, U>,
+        draw_mode: DrawMode,
+        max_vertices: usize,
+        max_indices: usize,
+    ) -> Self {
+        let vao = context.inner.create_vertex_array().unwrap();
+        context.inner.bind_vertex_array(Some(&vao));
+
+        let mut vbo = GlBuffer::new(context, WebGl2::ARRAY_BUFFER, MeshUsage::StreamDraw);
+        let mut ibo = GlBuffer::new(context, WebGl2::ELEMENT_ARRAY_BUFFER, MeshUsage::StreamDraw);
+        vbo.orphan(max_vertices * V::stride() as usize);
+        ibo.orphan(max_indices * IndexFormat::U32.size_in_bytes() as usize);
+
+        StreamingMesh {
+            vao,
+            vbo,
+            ibo,
+            context: context.clone(),
+            program: program.clone(),
+            phantom: PhantomData,
+            draw_mode,
+        }
+    }
+
+    /// Writes the contents of the `MeshBuilder` to the ring buffer and draws it. This can be
+    /// called any number of times per frame.
+    pub fn draw(
+        &mut self,
+        surface: &(impl Surface + ?Sized),
+        uniforms: &impl Uniforms<GlUniforms = U>,
+        builder: &MeshBuilder<V, P>,
+    ) {
+        let num_elements = builder.num_elements() as i32;
+        if num_elements == 0 {
+            return;
+        }
+
+        self.context.inner.bind_vertex_array(Some(&self.vao));
+
+        // This leaves the vertex buffer bound, so the attributes can be pointed at the data
+        let vertex_offset = self.vbo.append(&builder.vertex_data);
+        setup_vertex_attribs::<V>(
+            &self.context,
+            &self.program.inner.state.borrow().objects.program,
+            false,
+            vertex_offset as i32,
+        );
+
+        let index_format = if builder.is_indexed() {
+            let index_format = builder.index_format();
+            let index_offset = self.ibo.append(&index_format.convert(&builder.indices));
+            Some((index_format, index_offset))
+        } else {
+            None
+        };
+
+        let bound_program = self.program.bind(&self.context);
+        uniforms.update(&bound_program);
+        surface.bind(&self.context);
+        self.draw_mode.bind(&self.context);
+
+        match index_format {
+            Some((index_format, index_offset)) => self.context.inner.draw_elements_with_i32(
+                P::AS_GL,
+                num_elements,
+                index_format.as_gl(),
+                index_offset as i32,
+            ),
+            None => self.context.inner.draw_arrays(P::AS_GL, 0, num_elements),
+        }
+    }
+}
@@ -139,8 +139,7 @@ impl Texture2d {
         // TODO: add a method to generate mipmaps after data has been written to the texture
         assert!(!min_filter.has_mipmap());
 
-        let texture = context.inner.create_texture().unwrap();
-        context.inner.bind_texture(WebGl2::TEXTURE_2D, Some(&texture));
+        let (texture, id) = Self::create(context);
         context
             .inner
             .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
@@ -157,13 +156,7 @@ impl Texture2d {
             .unwrap();
         Self::set_tex_parameters(context, min_filter, mag_filter, wrap_mode);
 
-        Self {
-            texture,
-            size,
-            id: TextureId::new(),
-            context: context.clone(),
-            is_srgb: format.is_srgb(),
-        }
+        Self { texture, size, id, context: context.clone(), is_srgb: format.is_srgb() }
     }
 
     /// Creates a `Texture2d` from an `HtmlImageElement`.
@@ -175,8 +168,7 @@ impl Texture2d {
         mag_filter: MagFilter,
         wrap_mode: WrapMode,
     ) -> Self {
-        let texture = context.inner.create_texture().unwrap();
-        context.inner.bind_texture(WebGl2::TEXTURE_2D, Some(&texture));
+        let (texture, id) = Self::create(context);
 
         context
             .inner
@@ -195,7 +187,7 @@ impl Texture2d {
         Self {
             texture,
             size: vec2(image.width(), image.height()),
-            id: TextureId::new(),
+            id,
             context: context.clone(),
             is_srgb: format.is_srgb(),
         }
@@ -211,8 +203,7 @@ impl Texture2d {
         mag_filter: MagFilter,
         wrap_mode: WrapMode,
     ) -> Self {
-        let texture = context.inner.create_texture().unwrap();
-        context.inner.bind_texture(WebGl2::TEXTURE_2D, Some(&texture));
+        let (texture, id) = Self::create(context);
 
         context
             .inner
@@ -231,18 +222,11 @@ impl Texture2d {
 
         Self::set_tex_parameters(context, min_filter, mag_filter, wrap_mode);
 
-        Self {
-            texture,
-            size,
-            id: TextureId::new(),
-            context: context.clone(),
-            is_srgb: format.is_srgb(),
-        }
+        Self { texture, size, id, context: context.clone(), is_srgb: format.is_srgb() }
     }
 
     pub fn set_contents(&self, format: TextureFormat, data: &[u8]) {
-        // TODO: remove texture unit parameter
-        self.bind(0);
+        self.bind_for_upload();
         self.context
             .inner
             .tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
@@ -269,7 +253,7 @@ impl Texture2d {
         height: i32,
         data: &[u8],
     ) {
-        self.bind(0);
+        self.bind_for_upload();
         self.context
             .inner
             .tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
@@ -318,6 +302,36 @@ impl Texture2d {
         }
     }
 
+    /// Creates a texture and binds it to texture unit 0, so that it can be initialized.
+    fn create(context: &GlContext) -> (WebGlTexture, TextureId) {
+        let texture = context.inner.create_texture().unwrap();
+        let id = TextureId::new();
+        context.inner.active_texture(WebGl2::TEXTURE0);
+        context.inner.bind_texture(WebGl2::TEXTURE_2D, Some(&texture));
+        context.cache.borrow_mut().bound_textures[0] = Some((WebGl2::TEXTURE_2D, id));
+        (texture, id)
+    }
+
+    /// Binds the texture to a texture unit that isn't used by another sampler in the current
+    /// draw, and returns the unit.
+    pub(crate) fn bind_for_draw(&self) -> u32 {
+        let texture_unit =
+            self.context.cache.borrow_mut().claim_texture_unit((WebGl2::TEXTURE_2D, self.id));
+        self.bind(texture_unit);
+        texture_unit
+    }
+
+    /// Binds the texture to texture unit 0 and makes that unit active, so that it can be updated.
+    /// Unlike `bind`, this activates the unit even if the texture is already bound to it.
+    fn bind_for_upload(&self) {
+        self.context.inner.active_texture(WebGl2::TEXTURE0);
+        let mut cache = self.context.cache.borrow_mut();
+        if cache.bound_textures[0] != Some((WebGl2::TEXTURE_2D, self.id)) {
+            cache.bound_textures[0] = Some((WebGl2::TEXTURE_2D, self.id));
+            self.context.inner.bind_texture(WebGl2::TEXTURE_2D, Some(&self.texture));
+        }
+    }
+
     pub(crate) fn bind(&self, texture_unit: u32) {
         let mut cache = self.context.cache.borrow_mut();
         if cache.bound_textures[texture_unit as usize] != Some((WebGl2::TEXTURE_2D, self.id)) {
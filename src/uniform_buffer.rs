@@ -0,0 +1,96 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use uid::*;
+use web_sys::*;
+
+use crate::context::*;
+use crate::mesh::*;
+use crate::std140::*;
+
+#[doc(hidden)]
+#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
+pub(crate) struct UniformBufferId_(());
+
+pub(crate) type UniformBufferId = Id<UniformBufferId_>;
+
+/// A buffer holding the contents of a uniform block, which can be shared by any number of
+/// programs.
+///
+/// To use it, bind it to a binding point with `bind`, and call `GlProgram::bind_uniform_block`
+/// with the same binding point for each program that uses the block. The block must be declared
+/// with `layout(std140)` in the shader.
+///
+/// Example usage:
+/// ```
+/// #[derive(Std140)]
+/// pub struct CameraBlock {
+///     pub view_proj: Matrix4<f32>,
+///     pub eye_pos: Vector3<f32>,
+/// }
+///
+/// let camera_buffer = UniformBuffer::new(&context, &camera, MeshUsage::DynamicDraw);
+/// camera_buffer.bind(0);
+/// program.bind_uniform_block::<CameraBlock>("Camera", 0);
+/// ```
+pub struct UniformBuffer<T: Std140> {
+    buffer: WebGlBuffer,
+    id: UniformBufferId,
+    context: GlContext,
+    // Reused between calls to `set` to avoid reallocating
+    data: RefCell<Vec<u8>>,
+    phantom: PhantomData<T>,
+}
+
+impl<T: Std140> Drop for UniformBuffer<T> {
+    fn drop(&mut self) {
+        self.context.inner.delete_buffer(Some(&self.buffer));
+    }
+}
+
+impl<T: Std140> UniformBuffer<T> {
+    /// Creates a `UniformBuffer` holding the given value.
+    pub fn new(context: &GlContext, value: &T, usage: MeshUsage) -> Self {
+        let buffer = context.inner.create_buffer().unwrap();
+        let mut data = Vec::with_capacity(T::SIZE);
+        value.write_std140(&mut data);
+        debug_assert_eq!(data.len(), T::SIZE);
+
+        context.inner.bind_buffer(WebGl2::UNIFORM_BUFFER, Some(&buffer));
+        context.inner.buffer_data_with_u8_array(WebGl2::UNIFORM_BUFFER, &data, usage.as_gl());
+
+        UniformBuffer {
+            buffer,
+            id: UniformBufferId::new(),
+            context: context.clone(),
+            data: RefCell::new(data),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Replaces the contents of the buffer.
+    pub fn set(&self, value: &T) {
+        let mut data = self.data.borrow_mut();
+        data.clear();
+        value.write_std140(&mut data);
+
+        self.context.inner.bind_buffer(WebGl2::UNIFORM_BUFFER, Some(&self.buffer));
+        self.context.inner.buffer_sub_data_with_i32_and_u8_array(WebGl2::UNIFORM_BUFFER, 0, &data);
+    }
+
+    /// Binds the buffer to the given uniform buffer binding point.
+    pub fn bind(&self, binding: u32) {
+        let mut cache = self.context.cache.borrow_mut();
+        let binding = binding as usize;
+        if cache.bound_uniform_buffers.len() <= binding {
+            cache.bound_uniform_buffers.resize(binding + 1, None);
+        }
+        if cache.bound_uniform_buffers[binding] != Some(self.id) {
+            cache.bound_uniform_buffers[binding] = Some(self.id);
+            self.context.inner.bind_buffer_base(
+                WebGl2::UNIFORM_BUFFER,
+                binding as u32,
+                Some(&self.buffer),
+            );
+        }
+    }
+}
@@ -1,7 +1,13 @@
+use cgmath::*;
+use log::*;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::mem;
 use std::slice;
 use web_sys::*;
 
 use crate::context::*;
+use crate::program::*;
 use crate::texture::*;
 
 /// Holds uniforms for a given program.
@@ -14,23 +20,24 @@ use crate::texture::*;
 /// }
 ///
 /// struct ExampleUniformsGl {
-///     matrix: Matrix4Uniform,
+///     matrix: Uniform<Matrix4<f32>>,
 ///     tex: TextureUniform,
 /// }
 ///
 /// impl<'a> Uniforms for ExampleUniforms<'a> {
 ///     type GlUniforms = ExampleUniformsGl;
 ///
-///     fn update(&self, context: &GlContext, gl_uniforms: &Self::GlUniforms) {
-///         gl_uniforms.matrix.set(context, &self.matrix);
-///         gl_uniforms.tex.set(context, self.tex, 0);
+///     fn update(&self, program: &BoundProgram<Self::GlUniforms>) {
+///         let gl_uniforms = program.uniforms();
+///         gl_uniforms.matrix.set(program, &self.matrix);
+///         gl_uniforms.tex.set(program, self.tex);
 ///     }
 /// }
 ///
 /// impl GlUniforms for ExampleUniformsGl {
 ///     fn new(context: &GlContext, program: &WebGlProgram) -> Self {
 ///         ExampleUniformsGl {
-///             matrix: Matrix4Uniform::new("matrix", context, program),
+///             matrix: Uniform::new("matrix", context, program),
 ///             tex: TextureUniform::new("tex", context, program),
 ///         }
 ///     }
@@ -40,157 +47,425 @@ pub trait Uniforms {
     /// The `GlUniforms` instance corresponding to this `Uniforms`.
     type GlUniforms: GlUniforms;
 
-    /// Updates the given `GlUniforms` from this `Uniforms`. Should call `set` on each uniform
-    /// in the associated `GlUniforms`.
-    fn update(&self, context: &GlContext, gl_uniforms: &Self::GlUniforms);
+    /// Updates the bound program's `GlUniforms` from this `Uniforms`. Should call `set` on each
+    /// uniform in `program.uniforms()`.
+    fn update(&self, program: &BoundProgram<'_, Self::GlUniforms>);
 }
 
 /// A type used to hold the uniform locations, which can be updated from a corresponding instance of the `Uniforms` trait.
 ///
-/// See the `Uniforms` trait for an example implementation.
+/// See the `Uniforms` trait for an example implementation. Uniforms can only be looked up in
+/// `new` and `try_new`, since that's how they learn which program they belong to.
 pub trait GlUniforms {
     fn new(context: &GlContext, program: &WebGlProgram) -> Self;
+
+    /// Like `new`, but returns an error rather than panicking if a uniform can't be found.
+    /// `GlProgram::reload` uses this, so that an edit that removes a uniform (or makes the
+    /// compiler optimize one out) keeps the previous shaders.
+    ///
+    /// The default implementation calls `new`. Types that look their uniforms up with
+    /// `Uniform::new` or `TextureUniform::new` should implement this with the `try_new`
+    /// versions if their programs are reloaded:
+    /// ```
+    /// fn try_new(context: &GlContext, program: &WebGlProgram) -> Result<Self, String> {
+    ///     let missing = |name| format!("Uniform {} not found", name);
+    ///     Ok(ExampleUniformsGl {
+    ///         matrix: Uniform::try_new("matrix", context, program)
+    ///             .ok_or_else(|| missing("matrix"))?,
+    ///         tex: TextureUniform::try_new("tex", context, program)
+    ///             .ok_or_else(|| missing("tex"))?,
+    ///     })
+    /// }
+    /// ```
+    fn try_new(context: &GlContext, program: &WebGlProgram) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        Ok(Self::new(context, program))
+    }
 }
 
-// TODO: these structs are probably redundant
-pub struct Matrix4Uniform {
+/// A uniform of type `T`.
+///
+/// `T` can be a scalar (`f32`, `i32`, `u32`, or `bool`), a vector (a `cgmath` vector or point,
+/// or an array such as `[f32; 3]`), a matrix (a `cgmath` matrix or one of the non-square matrix
+/// types in this module), or a slice of any of those for a uniform array such as
+/// `vec4 lights[16]`.
+///
+/// The last value that was set is remembered, and setting the same value again doesn't call
+/// into GL. Since each program has its own `GlUniforms`, this skips redundant uploads when many
+/// draws with the same program share a value, such as a view-projection matrix. For values that
+/// change on every draw, `new_uncached` avoids the cost of comparing and storing them.
+pub struct Uniform<T: UniformValue + ?Sized> {
     loc: WebGlUniformLocation,
+    /// The program the uniform was looked up in, since the location is only valid for it.
+    program: ProgramId,
+    /// The bytes of the last value that was set, or `None` if the value shouldn't be cached.
+    last_value: Option<RefCell<Vec<u8>>>,
+    phantom: PhantomData<T>,
 }
 
-impl Matrix4Uniform {
+impl<T: UniformValue + ?Sized> Uniform<T> {
+    /// Looks up the uniform with the given name. Panics if the program has no such active
+    /// uniform; use `OptionalUniform` for uniforms that the shader compiler might remove.
     pub fn new(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
-        Self { loc: context.inner.get_uniform_location(program, name).unwrap() }
+        match Self::try_new(name, context, program) {
+            Some(uniform) => uniform,
+            None => {
+                error!("Uniform {} not found", name);
+                panic!();
+            }
+        }
+    }
+
+    /// Looks up the uniform with the given name, returning `None` if the program has no such
+    /// active uniform.
+    pub fn try_new(name: &str, context: &GlContext, program: &WebGlProgram) -> Option<Self> {
+        let loc = context.inner.get_uniform_location(program, name)?;
+        Some(Self {
+            loc,
+            program: constructing_program(context),
+            last_value: Some(RefCell::new(Vec::new())),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Like `new`, but the value is uploaded every time `set` is called.
+    pub fn new_uncached(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
+        let mut res = Self::new(name, context, program);
+        res.last_value = None;
+        res
     }
 
-    // TODO: guarantee that the program is bound when this is called
-    pub fn set(&self, context: &GlContext, mat: &impl AsRef<[f32; 16]>) {
-        // Unsafe is necessary because from_raw_parts is needed to construct a slice from a Mat4 (which is safe because Mat4 is repr(C))
-        context.inner.uniform_matrix4fv_with_f32_array(Some(&self.loc), false, unsafe {
-            slice::from_raw_parts(mat.as_ref() as *const f32, 16)
-        });
+    /// Sets the uniform. Panics if `program` isn't the program the uniform was looked up in.
+    pub fn set<U: GlUniforms>(&self, program: &BoundProgram<'_, U>, val: &T) {
+        check_program(self.program, program);
+        if let Some(last_value) = &self.last_value {
+            let bytes = val.as_bytes();
+            let mut last_value = last_value.borrow_mut();
+            // The cache starts out empty, and no value is zero bytes long (setting an empty
+            // array does nothing anyway), so the first value is always uploaded.
+            if *last_value == bytes {
+                return;
+            }
+            last_value.clear();
+            last_value.extend_from_slice(bytes);
+        }
+        val.set_uniform(program.context(), &self.loc);
     }
 }
 
-pub struct TextureUniform {
-    loc: WebGlUniformLocation,
+/// A uniform that might not exist in the program.
+///
+/// The GLSL compiler removes uniforms that don't affect the output, so a uniform can disappear
+/// when a line of shader code is commented out, or in a shader variant that doesn't use it. If
+/// the uniform is missing, a warning is logged when the `OptionalUniform` is created and `set`
+/// does nothing.
+pub struct OptionalUniform<T: UniformValue + ?Sized> {
+    inner: Option<Uniform<T>>,
 }
 
-impl TextureUniform {
+impl<T: UniformValue + ?Sized> OptionalUniform<T> {
     pub fn new(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
-        Self { loc: context.inner.get_uniform_location(program, name).unwrap() }
+        let inner = Uniform::try_new(name, context, program);
+        if inner.is_none() {
+            warn!("Uniform {} not found, setting it will have no effect", name);
+        }
+        Self { inner }
     }
 
-    // TODO: guarantee that the program is bound when this is called
-    pub fn set(&self, context: &GlContext, texture: &Texture2d, texture_unit: u32) {
-        context.inner.uniform1i(Some(&self.loc), texture_unit as i32);
-        texture.bind(texture_unit);
+    /// Like `new`, but the value is uploaded every time `set` is called.
+    pub fn new_uncached(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
+        let mut res = Self::new(name, context, program);
+        if let Some(inner) = &mut res.inner {
+            inner.last_value = None;
+        }
+        res
     }
-}
 
-pub struct Vector2Uniform {
-    loc: WebGlUniformLocation,
+    /// Returns whether the uniform exists in the program.
+    pub fn is_present(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    pub fn set<U: GlUniforms>(&self, program: &BoundProgram<'_, U>, val: &T) {
+        if let Some(inner) = &self.inner {
+            inner.set(program, val);
+        }
+    }
 }
 
-impl Vector2Uniform {
-    pub fn new(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
-        Self { loc: context.inner.get_uniform_location(program, name).unwrap() }
+// These aliases are kept so that code using the old uniform types still finds them, but their
+// `set` methods have changed: they take a `BoundProgram` rather than the context, and the value
+// by reference. The vector aliases no longer accept any `AsRef<[f32; N]>`, only the cgmath type.
+#[deprecated(note = "use `Uniform<f32>`; `set` now takes `&f32`")]
+pub type F32Uniform = Uniform<f32>;
+#[deprecated(note = "use `Uniform<Vector2<f32>>`; `set` now takes `&Vector2<f32>`")]
+pub type Vector2Uniform = Uniform<Vector2<f32>>;
+#[deprecated(note = "use `Uniform<Vector3<f32>>`; `set` now takes `&Vector3<f32>`")]
+pub type Vector3Uniform = Uniform<Vector3<f32>>;
+#[deprecated(note = "use `Uniform<Vector4<f32>>`; `set` now takes `&Vector4<f32>`")]
+pub type Vector4Uniform = Uniform<Vector4<f32>>;
+#[deprecated(note = "use `Uniform<[f32; 2]>`; `set` now takes `&[f32; 2]`")]
+pub type Array2Uniform = Uniform<[f32; 2]>;
+#[deprecated(note = "use `Uniform<[f32; 3]>`; `set` now takes `&[f32; 3]`")]
+pub type Array3Uniform = Uniform<[f32; 3]>;
+#[deprecated(note = "use `Uniform<[f32; 4]>`; `set` now takes `&[f32; 4]`")]
+pub type Array4Uniform = Uniform<[f32; 4]>;
+#[deprecated(note = "use `Uniform<Matrix4<f32>>`; `set` now takes `&Matrix4<f32>`")]
+pub type Matrix4Uniform = Uniform<Matrix4<f32>>;
+
+/// Returns the program whose `GlUniforms` are being created. Panics if uniforms are being looked
+/// up anywhere but in `GlUniforms::new` or `GlUniforms::try_new`.
+fn constructing_program(context: &GlContext) -> ProgramId {
+    match context.cache.borrow().constructing_program {
+        Some(id) => id,
+        None => {
+            error!("Uniforms can only be looked up in GlUniforms::new or GlUniforms::try_new");
+            panic!();
+        }
     }
+}
 
-    // TODO: guarantee that the program is bound when this is called
-    pub fn set(&self, context: &GlContext, val: &impl AsRef<[f32; 2]>) {
-        let val = val.as_ref();
-        context.inner.uniform2f(Some(&self.loc), val[0], val[1]);
+/// Panics if a uniform from `uniform_program` is being set while another program is bound.
+fn check_program<U: GlUniforms>(uniform_program: ProgramId, program: &BoundProgram<'_, U>) {
+    if program.id() != uniform_program {
+        error!("Uniform was set for a different program than the one it was looked up in");
+        panic!();
     }
 }
 
-pub struct Vector3Uniform {
-    loc: WebGlUniformLocation,
+/// A value that can be stored in a `Uniform`.
+pub trait UniformValue {
+    /// Uploads the value to the uniform at the given location. The program must be bound.
+    fn set_uniform(&self, context: &GlContext, loc: &WebGlUniformLocation);
+
+    /// Returns the bytes of the value, which are used to detect when it hasn't changed.
+    fn as_bytes(&self) -> &[u8];
 }
 
-impl Vector3Uniform {
-    pub fn new(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
-        Self { loc: context.inner.get_uniform_location(program, name).unwrap() }
-    }
+/// A value that can be an element of a uniform array. Uniform arrays are set from slices.
+///
+/// # Safety
+///
+/// The bytes of the values are read directly to detect when they haven't changed, so implementors
+/// must not contain padding or any other uninitialized bytes.
+pub unsafe trait UniformArrayElement: Sized {
+    /// Uploads the values to the uniform array at the given location. The program must be
+    /// bound.
+    fn set_uniform_array(values: &[Self], context: &GlContext, loc: &WebGlUniformLocation);
+}
 
-    // TODO: guarantee that the program is bound when this is called
-    pub fn set(&self, context: &GlContext, val: &impl AsRef<[f32; 3]>) {
-        let val = val.as_ref();
-        context.inner.uniform3f(Some(&self.loc), val[0], val[1], val[2]);
+impl<T: UniformArrayElement> UniformValue for T {
+    fn set_uniform(&self, context: &GlContext, loc: &WebGlUniformLocation) {
+        T::set_uniform_array(slice::from_ref(self), context, loc);
     }
-}
 
-pub struct Vector4Uniform {
-    loc: WebGlUniformLocation,
+    fn as_bytes(&self) -> &[u8] {
+        slice::from_ref(self).as_bytes()
+    }
 }
 
-impl Vector4Uniform {
-    pub fn new(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
-        Self { loc: context.inner.get_uniform_location(program, name).unwrap() }
+impl<T: UniformArrayElement> UniformValue for [T] {
+    fn set_uniform(&self, context: &GlContext, loc: &WebGlUniformLocation) {
+        T::set_uniform_array(self, context, loc);
     }
 
-    // TODO: guarantee that the program is bound when this is called
-    pub fn set(&self, context: &GlContext, val: &impl AsRef<[f32; 4]>) {
-        let val = val.as_ref();
-        context.inner.uniform4f(Some(&self.loc), val[0], val[1], val[2], val[3]);
+    fn as_bytes(&self) -> &[u8] {
+        // SAFETY: `UniformArrayElement` requires that the elements have no uninitialized bytes.
+        unsafe { slice::from_raw_parts(self.as_ptr() as *const u8, mem::size_of_val(self)) }
     }
 }
 
-pub struct Array2Uniform {
-    loc: WebGlUniformLocation,
+/// Reinterprets a slice of values, each made of `n` scalars, as a slice of scalars.
+///
+/// This is unsafe because `T` must consist of exactly `n` values of type `S`, without padding.
+/// This is true of arrays, and of `cgmath` types because they're `repr(C)`.
+unsafe fn flatten<T, S>(values: &[T], n: usize) -> &[S] {
+    debug_assert_eq!(mem::size_of::<T>(), n * mem::size_of::<S>());
+    slice::from_raw_parts(values.as_ptr() as *const S, values.len() * n)
 }
 
-impl Array2Uniform {
-    pub fn new(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
-        Self { loc: context.inner.get_uniform_location(program, name).unwrap() }
-    }
+macro_rules! impl_uniform_array_element {
+    ($method:ident, $scalar:ty, $n:expr, $($ty:ty),*) => {
+        $(
+            unsafe impl UniformArrayElement for $ty {
+                fn set_uniform_array(
+                    values: &[Self],
+                    context: &GlContext,
+                    loc: &WebGlUniformLocation,
+                ) {
+                    let data = unsafe { flatten::<_, $scalar>(values, $n) };
+                    context.inner.$method(Some(loc), data);
+                }
+            }
+        )*
+    };
+}
 
-    // TODO: guarantee that the program is bound when this is called
-    pub fn set(&self, context: &GlContext, val: [f32; 2]) {
-        context.inner.uniform2f(Some(&self.loc), val[0], val[1]);
-    }
+macro_rules! impl_uniform_array_element_matrix {
+    ($method:ident, $n:expr, $($ty:ty),*) => {
+        $(
+            unsafe impl UniformArrayElement for $ty {
+                fn set_uniform_array(
+                    values: &[Self],
+                    context: &GlContext,
+                    loc: &WebGlUniformLocation,
+                ) {
+                    let data = unsafe { flatten::<_, f32>(values, $n) };
+                    context.inner.$method(Some(loc), false, data);
+                }
+            }
+        )*
+    };
 }
 
-pub struct Array3Uniform {
-    loc: WebGlUniformLocation,
+impl_uniform_array_element!(uniform1fv_with_f32_array, f32, 1, f32);
+impl_uniform_array_element!(uniform2fv_with_f32_array, f32, 2, [f32; 2], Vector2<f32>, Point2<f32>);
+impl_uniform_array_element!(uniform3fv_with_f32_array, f32, 3, [f32; 3], Vector3<f32>, Point3<f32>);
+impl_uniform_array_element!(uniform4fv_with_f32_array, f32, 4, [f32; 4], Vector4<f32>);
+
+impl_uniform_array_element!(uniform1iv_with_i32_array, i32, 1, i32);
+impl_uniform_array_element!(uniform2iv_with_i32_array, i32, 2, [i32; 2], Vector2<i32>, Point2<i32>);
+impl_uniform_array_element!(uniform3iv_with_i32_array, i32, 3, [i32; 3], Vector3<i32>, Point3<i32>);
+impl_uniform_array_element!(uniform4iv_with_i32_array, i32, 4, [i32; 4], Vector4<i32>);
+
+impl_uniform_array_element!(uniform1uiv_with_u32_array, u32, 1, u32);
+impl_uniform_array_element!(
+    uniform2uiv_with_u32_array,
+    u32,
+    2,
+    [u32; 2],
+    Vector2<u32>,
+    Point2<u32>
+);
+impl_uniform_array_element!(
+    uniform3uiv_with_u32_array,
+    u32,
+    3,
+    [u32; 3],
+    Vector3<u32>,
+    Point3<u32>
+);
+impl_uniform_array_element!(uniform4uiv_with_u32_array, u32, 4, [u32; 4], Vector4<u32>);
+
+impl_uniform_array_element_matrix!(uniform_matrix2fv_with_f32_array, 4, Matrix2<f32>);
+impl_uniform_array_element_matrix!(uniform_matrix3fv_with_f32_array, 9, Matrix3<f32>);
+impl_uniform_array_element_matrix!(uniform_matrix4fv_with_f32_array, 16, Matrix4<f32>);
+impl_uniform_array_element_matrix!(uniform_matrix2x3fv_with_f32_array, 6, Matrix2x3);
+impl_uniform_array_element_matrix!(uniform_matrix2x4fv_with_f32_array, 8, Matrix2x4);
+impl_uniform_array_element_matrix!(uniform_matrix3x2fv_with_f32_array, 6, Matrix3x2);
+impl_uniform_array_element_matrix!(uniform_matrix3x4fv_with_f32_array, 12, Matrix3x4);
+impl_uniform_array_element_matrix!(uniform_matrix4x2fv_with_f32_array, 8, Matrix4x2);
+impl_uniform_array_element_matrix!(uniform_matrix4x3fv_with_f32_array, 12, Matrix4x3);
+
+// Bools have to be converted to ints, since they aren't the same size.
+macro_rules! impl_uniform_array_element_bool {
+    ($method:ident, $n:expr, $($ty:ty),*) => {
+        $(
+            unsafe impl UniformArrayElement for $ty {
+                fn set_uniform_array(
+                    values: &[Self],
+                    context: &GlContext,
+                    loc: &WebGlUniformLocation,
+                ) {
+                    let bools = unsafe { flatten::<_, bool>(values, $n) };
+                    let data: Vec<i32> = bools.iter().map(|&x| x as i32).collect();
+                    context.inner.$method(Some(loc), &data);
+                }
+            }
+        )*
+    };
 }
 
-impl Array3Uniform {
-    pub fn new(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
-        Self { loc: context.inner.get_uniform_location(program, name).unwrap() }
-    }
+impl_uniform_array_element_bool!(uniform1iv_with_i32_array, 1, bool);
+impl_uniform_array_element_bool!(uniform2iv_with_i32_array, 2, [bool; 2], Vector2<bool>);
+impl_uniform_array_element_bool!(uniform3iv_with_i32_array, 3, [bool; 3], Vector3<bool>);
+impl_uniform_array_element_bool!(uniform4iv_with_i32_array, 4, [bool; 4], Vector4<bool>);
 
-    // TODO: guarantee that the program is bound when this is called
-    pub fn set(&self, context: &GlContext, val: [f32; 3]) {
-        context.inner.uniform3f(Some(&self.loc), val[0], val[1], val[2]);
-    }
+macro_rules! non_square_matrix {
+    ($name:ident, $glsl_name:expr, $cols:expr, $rows:expr) => {
+        #[doc = "A non-square matrix, for `"]
+        #[doc = $glsl_name]
+        #[doc = "` uniforms. Stored as an array of columns."]
+        #[repr(C)]
+        #[derive(Copy, Clone, Debug, PartialEq)]
+        pub struct $name(pub [[f32; $rows]; $cols]);
+    };
 }
 
-pub struct Array4Uniform {
+non_square_matrix!(Matrix2x3, "mat2x3", 2, 3);
+non_square_matrix!(Matrix2x4, "mat2x4", 2, 4);
+non_square_matrix!(Matrix3x2, "mat3x2", 3, 2);
+non_square_matrix!(Matrix3x4, "mat3x4", 3, 4);
+non_square_matrix!(Matrix4x2, "mat4x2", 4, 2);
+non_square_matrix!(Matrix4x3, "mat4x3", 4, 3);
+
+pub struct TextureUniform {
     loc: WebGlUniformLocation,
+    /// The program the uniform was looked up in; see `Uniform`.
+    program: ProgramId,
+    /// The texture unit that the sampler was last set to.
+    last_unit: Cell<Option<u32>>,
 }
 
-impl Array4Uniform {
+impl TextureUniform {
+    /// Looks up the sampler uniform with the given name. Panics if the program has no such
+    /// active uniform; use `OptionalTextureUniform` for samplers that the shader compiler might
+    /// remove.
     pub fn new(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
-        Self { loc: context.inner.get_uniform_location(program, name).unwrap() }
+        match Self::try_new(name, context, program) {
+            Some(uniform) => uniform,
+            None => {
+                error!("Uniform {} not found", name);
+                panic!();
+            }
+        }
+    }
+
+    /// Looks up the sampler uniform with the given name, returning `None` if the program has
+    /// no such active uniform.
+    pub fn try_new(name: &str, context: &GlContext, program: &WebGlProgram) -> Option<Self> {
+        let loc = context.inner.get_uniform_location(program, name)?;
+        Some(Self { loc, program: constructing_program(context), last_unit: Cell::new(None) })
     }
 
-    // TODO: guarantee that the program is bound when this is called
-    pub fn set(&self, context: &GlContext, val: [f32; 4]) {
-        context.inner.uniform4f(Some(&self.loc), val[0], val[1], val[2], val[3]);
+    /// Binds the texture to a texture unit and points the sampler at it. Units are assigned
+    /// automatically, so that every sampler set in the same draw gets a different unit unless
+    /// they use the same texture. Panics if `program` isn't the program the uniform was looked
+    /// up in.
+    pub fn set<U: GlUniforms>(&self, program: &BoundProgram<'_, U>, texture: &Texture2d) {
+        check_program(self.program, program);
+        let texture_unit = texture.bind_for_draw();
+        if self.last_unit.get() != Some(texture_unit) {
+            program.context().inner.uniform1i(Some(&self.loc), texture_unit as i32);
+            self.last_unit.set(Some(texture_unit));
+        }
     }
 }
 
-pub struct F32Uniform {
-    loc: WebGlUniformLocation,
+/// A sampler uniform that might not exist in the program. See `OptionalUniform`.
+pub struct OptionalTextureUniform {
+    inner: Option<TextureUniform>,
 }
 
-impl F32Uniform {
+impl OptionalTextureUniform {
     pub fn new(name: &str, context: &GlContext, program: &WebGlProgram) -> Self {
-        Self { loc: context.inner.get_uniform_location(program, name).unwrap() }
+        let inner = TextureUniform::try_new(name, context, program);
+        if inner.is_none() {
+            warn!("Uniform {} not found, setting it will have no effect", name);
+        }
+        Self { inner }
+    }
+
+    /// Returns whether the uniform exists in the program.
+    pub fn is_present(&self) -> bool {
+        self.inner.is_some()
     }
 
-    // TODO: guarantee that the program is bound when this is called
-    pub fn set(&self, context: &GlContext, val: f32) {
-        context.inner.uniform1f(Some(&self.loc), val);
+    pub fn set<U: GlUniforms>(&self, program: &BoundProgram<'_, U>, texture: &Texture2d) {
+        if let Some(inner) = &self.inner {
+            inner.set(program, texture);
+        }
     }
 }
@@ -0,0 +1,557 @@
+use cgmath::*;
+use web_sys::*;
+
+use crate::context::*;
+
+/// The type of each component of a vertex attribute, as it's stored in the vertex buffer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ComponentType {
+    F32,
+    /// A half float; see `F16`.
+    F16,
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    /// Four signed components packed into a single `u32`; see `Packed2_10_10_10`.
+    I2_10_10_10,
+    /// Four unsigned components packed into a single `u32`; see `Packed2_10_10_10`.
+    U2_10_10_10,
+}
+
+impl ComponentType {
+    fn as_gl(self) -> u32 {
+        match self {
+            ComponentType::F32 => WebGl2::FLOAT,
+            ComponentType::F16 => WebGl2::HALF_FLOAT,
+            ComponentType::I8 => WebGl2::BYTE,
+            ComponentType::U8 => WebGl2::UNSIGNED_BYTE,
+            ComponentType::I16 => WebGl2::SHORT,
+            ComponentType::U16 => WebGl2::UNSIGNED_SHORT,
+            ComponentType::I32 => WebGl2::INT,
+            ComponentType::U32 => WebGl2::UNSIGNED_INT,
+            ComponentType::I2_10_10_10 => WebGl2::INT_2_10_10_10_REV,
+            ComponentType::U2_10_10_10 => WebGl2::UNSIGNED_INT_2_10_10_10_REV,
+        }
+    }
+
+    /// The size of a single component in bytes. Packed types are treated as a single
+    /// component.
+    fn size_in_bytes(self) -> i32 {
+        match self {
+            ComponentType::I8 | ComponentType::U8 => 1,
+            ComponentType::F16 | ComponentType::I16 | ComponentType::U16 => 2,
+            ComponentType::F32
+            | ComponentType::I32
+            | ComponentType::U32
+            | ComponentType::I2_10_10_10
+            | ComponentType::U2_10_10_10 => 4,
+        }
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, ComponentType::F32 | ComponentType::F16)
+    }
+
+    fn is_packed(self) -> bool {
+        matches!(self, ComponentType::I2_10_10_10 | ComponentType::U2_10_10_10)
+    }
+}
+
+/// How the components of an attribute are converted to the type used in the shader.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AttributeConversion {
+    /// The shader sees a float. Integers are converted directly, so 255u8 becomes 255.0.
+    Float,
+    /// The shader sees a float. Integers are normalized, so unsigned types are mapped to
+    /// [0, 1] and signed types to [-1, 1].
+    Normalized,
+    /// The shader sees an integer (an `int`, `uint`, `ivec`, or `uvec`). Only valid for
+    /// non-packed integer types, and not for matrices.
+    Integer,
+}
+
+/// The GLSL type of a vertex attribute.
+///
+/// Matrices are stored in column-major order, and each column takes up its own attribute
+/// location, so a `mat3` attribute at location 2 also uses locations 3 and 4.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AttributeType {
+    Scalar,
+    Vec2,
+    Vec3,
+    Vec4,
+    Mat2,
+    Mat2x3,
+    Mat2x4,
+    Mat3x2,
+    Mat3,
+    Mat3x4,
+    Mat4x2,
+    Mat4x3,
+    Mat4,
+}
+
+impl AttributeType {
+    /// The number of columns, which is also the number of attribute locations used. This is 1
+    /// for scalars and vectors.
+    pub fn columns(self) -> i32 {
+        match self {
+            AttributeType::Scalar
+            | AttributeType::Vec2
+            | AttributeType::Vec3
+            | AttributeType::Vec4 => 1,
+            AttributeType::Mat2 | AttributeType::Mat2x3 | AttributeType::Mat2x4 => 2,
+            AttributeType::Mat3x2 | AttributeType::Mat3 | AttributeType::Mat3x4 => 3,
+            AttributeType::Mat4x2 | AttributeType::Mat4x3 | AttributeType::Mat4 => 4,
+        }
+    }
+
+    /// The number of components in each column.
+    pub fn rows(self) -> i32 {
+        match self {
+            AttributeType::Scalar => 1,
+            AttributeType::Vec2
+            | AttributeType::Mat2
+            | AttributeType::Mat3x2
+            | AttributeType::Mat4x2 => 2,
+            AttributeType::Vec3
+            | AttributeType::Mat2x3
+            | AttributeType::Mat3
+            | AttributeType::Mat4x3 => 3,
+            AttributeType::Vec4
+            | AttributeType::Mat2x4
+            | AttributeType::Mat3x4
+            | AttributeType::Mat4 => 4,
+        }
+    }
+
+    fn is_matrix(self) -> bool {
+        self.columns() > 1
+    }
+}
+
+/// A vertex attribute.
+#[derive(Copy, Clone, Debug)]
+pub struct Attribute {
+    /// The attribute's name in the shader.
+    pub name: &'static str,
+    pub ty: AttributeType,
+    pub component_type: ComponentType,
+    pub conversion: AttributeConversion,
+}
+
+impl Attribute {
+    pub const fn new(
+        name: &'static str,
+        ty: AttributeType,
+        component_type: ComponentType,
+        conversion: AttributeConversion,
+    ) -> Self {
+        Attribute { name, ty, component_type, conversion }
+    }
+
+    /// An attribute made of `f32`s.
+    pub const fn f32(name: &'static str, ty: AttributeType) -> Self {
+        Self::new(name, ty, ComponentType::F32, AttributeConversion::Float)
+    }
+
+    /// The size of each column in bytes.
+    fn column_size_in_bytes(&self) -> i32 {
+        if self.component_type.is_packed() {
+            self.component_type.size_in_bytes()
+        } else {
+            self.ty.rows() * self.component_type.size_in_bytes()
+        }
+    }
+
+    /// The size of the attribute in bytes.
+    pub fn size_in_bytes(&self) -> i32 {
+        self.ty.columns() * self.column_size_in_bytes()
+    }
+
+    /// Panics if the attribute's component type and conversion can't be used together.
+    fn validate(&self) {
+        let valid = match self.conversion {
+            AttributeConversion::Float => true,
+            AttributeConversion::Normalized => !self.component_type.is_float(),
+            AttributeConversion::Integer => {
+                !self.component_type.is_float()
+                    && !self.component_type.is_packed()
+                    && !self.ty.is_matrix()
+            }
+        };
+        assert!(
+            valid,
+            "Attribute {} can't use {:?} with {:?}",
+            self.name, self.component_type, self.conversion
+        );
+        assert!(
+            !self.component_type.is_packed() || self.ty == AttributeType::Vec4,
+            "Attribute {} uses a packed type, so it must be a Vec4",
+            self.name
+        );
+    }
+}
+
+/// A list of all OpenGL attributes for a given program.
+pub type Attributes = &'static [Attribute];
+
+/// A vertex for a given program.
+///
+/// Example implementation:
+/// ```
+/// struct ExampleVertex {
+///     pos: Vector2<f32>,
+///     uv: Vector2<f32>,
+///     color: [u8; 4],
+/// }
+///
+/// impl VertexData for ExampleVertex {
+///     const ATTRIBUTES: Attributes = &[
+///         Attribute::f32("pos", AttributeType::Vec2),
+///         Attribute::f32("uv", AttributeType::Vec2),
+///         Attribute::new(
+///             "color",
+///             AttributeType::Vec4,
+///             ComponentType::U8,
+///             AttributeConversion::Normalized,
+///         ),
+///     ];
+/// }
+///
+/// impl VertexComponent for ExampleVertex {
+///     fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+///         self.pos.add_to_mesh(f);
+///         self.uv.add_to_mesh(f);
+///         self.color.add_to_mesh(f);
+///     }
+/// }
+/// ```
+pub trait Vertex: VertexData + VertexComponent {}
+
+impl<T: VertexData + VertexComponent> Vertex for T {}
+
+pub trait VertexData {
+    /// A list of all OpenGL attributes that each vertex contains.
+    ///
+    /// Each attribute's offset within the vertex, and the size of the whole vertex, must be a
+    /// multiple of the attribute's component size; add padding if necessary.
+    const ATTRIBUTES: Attributes;
+
+    /// The size of each vertex in bytes.
+    // TODO: find a way to cache this
+    fn stride() -> i32 {
+        Self::ATTRIBUTES.iter().map(|attr| attr.size_in_bytes()).sum()
+    }
+}
+
+/// A component of a vertex.
+///
+/// See the `Vertex` trait for an example implementation.
+pub trait VertexComponent {
+    /// Adds the `VertexComponent` to a mesh by calling the given closure with the bytes of each
+    /// component, in order. Composite `VertexComponent` instances can call `add_to_mesh` for
+    /// each of their components rather than calling the closure directly.
+    fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8]));
+}
+
+macro_rules! impl_vertex_component_scalar {
+    ($($ty:ty),*) => {
+        $(
+            impl VertexComponent for $ty {
+                fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+                    f(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_vertex_component_scalar!(f32, i8, u8, i16, u16, i32, u32);
+
+macro_rules! impl_vertex_component_vector {
+    ($ty:ident, $($field:ident),*) => {
+        impl<T: VertexComponent> VertexComponent for $ty<T> {
+            fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+                $(self.$field.add_to_mesh(f);)*
+            }
+        }
+    };
+}
+
+impl_vertex_component_vector!(Vector2, x, y);
+impl_vertex_component_vector!(Vector3, x, y, z);
+impl_vertex_component_vector!(Vector4, x, y, z, w);
+impl_vertex_component_vector!(Point2, x, y);
+impl_vertex_component_vector!(Point3, x, y, z);
+impl_vertex_component_vector!(Matrix2, x, y);
+impl_vertex_component_vector!(Matrix3, x, y, z);
+impl_vertex_component_vector!(Matrix4, x, y, z, w);
+
+impl<T: VertexComponent, const N: usize> VertexComponent for [T; N] {
+    fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+        for x in self {
+            x.add_to_mesh(f);
+        }
+    }
+}
+
+/// A 16-bit float, for use with `ComponentType::F16`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct F16(pub u16);
+
+impl F16 {
+    /// Converts an `f32` to the nearest `F16`. Values that are too large become infinity.
+    pub fn from_f32(value: f32) -> Self {
+        let bits = value.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exponent = ((bits >> 23) & 0xff) as i32;
+        let mantissa = bits & 0x7f_ffff;
+
+        // Infinity and NaN
+        if exponent == 0xff {
+            return F16(sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 });
+        }
+
+        let exponent = exponent - 127 + 15;
+        if exponent >= 0x1f {
+            return F16(sign | 0x7c00);
+        }
+
+        // The number of mantissa bits that are discarded, and the magnitude without them. If the
+        // result is subnormal, the implicit leading 1 has to be included and more bits are lost.
+        let (shift, magnitude) = if exponent <= 0 {
+            if exponent < -10 {
+                return F16(sign);
+            }
+            let shift = (14 - exponent) as u32;
+            (shift, (mantissa | 0x80_0000) >> shift)
+        } else {
+            (13, ((exponent as u32) << 10) | (mantissa >> 13))
+        };
+
+        // Round to nearest, with ties to even. If this carries into the exponent, the result is
+        // still correct.
+        let full_mantissa = if exponent <= 0 { mantissa | 0x80_0000 } else { mantissa };
+        let round_bit = 1 << (shift - 1);
+        let round_up = full_mantissa & round_bit != 0
+            && (full_mantissa & (round_bit - 1) != 0 || magnitude & 1 != 0);
+        F16(sign | (magnitude + round_up as u32) as u16)
+    }
+}
+
+impl VertexComponent for F16 {
+    fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+        self.0.add_to_mesh(f);
+    }
+}
+
+/// Four components packed into a `u32`, for use with `ComponentType::I2_10_10_10` or
+/// `ComponentType::U2_10_10_10`. The first three components have 10 bits each, and the last
+/// one has 2 bits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Packed2_10_10_10(pub u32);
+
+impl Packed2_10_10_10 {
+    /// Packs four values in [-1, 1], for use with `ComponentType::I2_10_10_10` and
+    /// `AttributeConversion::Normalized`. Useful for normals and tangents.
+    pub fn from_signed_normalized(values: [f32; 4]) -> Self {
+        let pack = |value: f32, max: f32, bits: u32| {
+            let value = (value.clamp(-1.0, 1.0) * max).round() as i32;
+            (value as u32) & ((1 << bits) - 1)
+        };
+        Packed2_10_10_10(
+            pack(values[0], 511.0, 10)
+                | pack(values[1], 511.0, 10) << 10
+                | pack(values[2], 511.0, 10) << 20
+                | pack(values[3], 1.0, 2) << 30,
+        )
+    }
+
+    /// Packs four values in [0, 1], for use with `ComponentType::U2_10_10_10` and
+    /// `AttributeConversion::Normalized`.
+    pub fn from_unsigned_normalized(values: [f32; 4]) -> Self {
+        let pack = |value: f32, max: f32| (value.clamp(0.0, 1.0) * max).round() as u32;
+        Packed2_10_10_10(
+            pack(values[0], 1023.0)
+                | pack(values[1], 1023.0) << 10
+                | pack(values[2], 1023.0) << 20
+                | pack(values[3], 3.0) << 30,
+        )
+    }
+}
+
+impl VertexComponent for Packed2_10_10_10 {
+    fn add_to_mesh(&self, f: &mut dyn FnMut(&[u8])) {
+        self.0.add_to_mesh(f);
+    }
+}
+
+/// A monomorphized `setup_vertex_attribs`, so the attributes of a vertex type can be set up
+/// again for another program without knowing the type.
+pub(crate) type SetupVertexAttribs = fn(&GlContext, &WebGlProgram, bool, i32);
+
+/// Returns the bytes of the vertices, as they're stored in a vertex buffer.
+pub(crate) fn vertex_bytes<V: Vertex>(verts: &[V]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(verts.len() * V::stride() as usize);
+    for vert in verts {
+        vert.add_to_mesh(&mut |bytes| res.extend_from_slice(bytes));
+    }
+    res
+}
+
+/// Enables the attributes in `D::ATTRIBUTES` and points them at the currently bound
+/// `ARRAY_BUFFER`, with the first vertex starting `base_offset` bytes into the buffer.
+pub(crate) fn setup_vertex_attribs<D: VertexData>(
+    context: &GlContext,
+    program: &WebGlProgram,
+    instanced: bool,
+    base_offset: i32,
+) {
+    let stride = D::stride();
+    let mut offset = base_offset;
+    for attr in D::ATTRIBUTES.iter() {
+        attr.validate();
+        let component_size = attr.component_type.size_in_bytes();
+        assert!(
+            offset % component_size == 0 && stride % component_size == 0,
+            "Attribute {} is misaligned; add padding so its offset and the vertex size are \
+             multiples of {}",
+            attr.name,
+            component_size
+        );
+
+        let loc = context.inner.get_attrib_location(program, attr.name);
+        // Attributes that the program doesn't use are skipped, so a mesh can be drawn by
+        // programs that only need some of its attributes
+        if loc < 0 {
+            offset += attr.size_in_bytes();
+            continue;
+        }
+        let loc = loc as u32;
+
+        // Each column of a matrix is specified separately, in consecutive locations.
+        let column_size = attr.column_size_in_bytes();
+        for i in 0..attr.ty.columns() {
+            setup_vertex_attrib(
+                context,
+                loc + i as u32,
+                attr,
+                stride,
+                offset + i * column_size,
+                instanced,
+            );
+        }
+
+        offset += attr.size_in_bytes();
+    }
+}
+
+fn setup_vertex_attrib(
+    context: &GlContext,
+    loc: u32,
+    attr: &Attribute,
+    stride: i32,
+    offset: i32,
+    instanced: bool,
+) {
+    context.inner.enable_vertex_attrib_array(loc);
+    let size = attr.ty.rows();
+    let gl_type = attr.component_type.as_gl();
+    match attr.conversion {
+        AttributeConversion::Integer => {
+            context.inner.vertex_attrib_i_pointer_with_i32(loc, size, gl_type, stride, offset);
+        }
+        AttributeConversion::Float | AttributeConversion::Normalized => {
+            context.inner.vertex_attrib_pointer_with_i32(
+                loc,
+                size,
+                gl_type,
+                attr.conversion == AttributeConversion::Normalized,
+                stride,
+                offset,
+            );
+        }
+    }
+    if instanced {
+        context.inner.vertex_attrib_divisor(loc, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f16_bits(value: f32) -> u16 {
+        F16::from_f32(value).0
+    }
+
+    #[test]
+    fn f16_normal_values() {
+        assert_eq!(f16_bits(0.0), 0x0000);
+        assert_eq!(f16_bits(-0.0), 0x8000);
+        assert_eq!(f16_bits(1.0), 0x3c00);
+        assert_eq!(f16_bits(-2.0), 0xc000);
+        assert_eq!(f16_bits(0.5), 0x3800);
+        assert_eq!(f16_bits(65504.0), 0x7bff);
+        // The smallest normal value
+        assert_eq!(f16_bits(2.0f32.powi(-14)), 0x0400);
+    }
+
+    #[test]
+    fn f16_rounds_to_nearest_even() {
+        // Halfway between 1 and the next value rounds down to the even mantissa
+        assert_eq!(f16_bits(1.0 + 2.0f32.powi(-11)), 0x3c00);
+        // Halfway between an odd mantissa and the next value rounds up
+        assert_eq!(f16_bits(1.0 + 3.0 * 2.0f32.powi(-11)), 0x3c02);
+        // Slightly over halfway rounds up
+        assert_eq!(f16_bits(1.0 + 2.0f32.powi(-11) + 2.0f32.powi(-20)), 0x3c01);
+        // Rounding up can carry into the exponent
+        assert_eq!(f16_bits(2.0 - 2.0f32.powi(-12)), 0x4000);
+    }
+
+    #[test]
+    fn f16_subnormals() {
+        assert_eq!(f16_bits(2.0f32.powi(-24)), 0x0001);
+        assert_eq!(f16_bits(-2.0f32.powi(-24)), 0x8001);
+        assert_eq!(f16_bits(1023.0 * 2.0f32.powi(-24)), 0x03ff);
+        assert_eq!(f16_bits(3.0 * 2.0f32.powi(-24)), 0x0003);
+        // Halfway between 0 and the smallest subnormal rounds to 0, but anything above it
+        // doesn't
+        assert_eq!(f16_bits(2.0f32.powi(-25)), 0x0000);
+        assert_eq!(f16_bits(1.5 * 2.0f32.powi(-25)), 0x0001);
+        assert_eq!(f16_bits(2.0f32.powi(-26)), 0x0000);
+        assert_eq!(f16_bits(-2.0f32.powi(-30)), 0x8000);
+        // The largest subnormal rounds up to the smallest normal value
+        assert_eq!(f16_bits(1023.75 * 2.0f32.powi(-24)), 0x0400);
+    }
+
+    #[test]
+    fn f16_infinity_and_nan() {
+        assert_eq!(f16_bits(f32::INFINITY), 0x7c00);
+        assert_eq!(f16_bits(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f16_bits(1.0e6), 0x7c00);
+        assert_eq!(f16_bits(-1.0e6), 0xfc00);
+        // Rounds up past the largest finite value
+        assert_eq!(f16_bits(65520.0), 0x7c00);
+        let nan = f16_bits(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x3ff, 0);
+    }
+
+    #[test]
+    fn packed_2_10_10_10() {
+        assert_eq!(Packed2_10_10_10::from_signed_normalized([0.0; 4]).0, 0);
+        assert_eq!(
+            Packed2_10_10_10::from_signed_normalized([1.0, -1.0, 2.0, -1.0]).0,
+            511 | 0x201 << 10 | 511 << 20 | 0b11 << 30
+        );
+        assert_eq!(
+            Packed2_10_10_10::from_unsigned_normalized([1.0, 0.0, 0.5, 1.0]).0,
+            1023 | 512 << 20 | 3 << 30
+        );
+    }
+}
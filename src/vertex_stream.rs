@@ -0,0 +1,72 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use crate::buffer::*;
+use crate::context::*;
+use crate::mesh::*;
+use crate::vertex::*;
+
+/// A vertex buffer holding some of a mesh's attributes, separately from the buffer built by its
+/// `MeshBuilder`. Streams are attached to a mesh with `Mesh::attach_stream`.
+///
+/// Splitting attributes between streams allows attributes that change often, such as positions
+/// updated by CPU skinning, to be uploaded without re-uploading the static ones. Each stream has
+/// its own `MeshUsage`, and can be interleaved (several attributes per stream) or planar (one
+/// attribute per stream).
+///
+/// A stream must contain at least as many vertices as each mesh it's attached to when the mesh
+/// is drawn. Meshes share the stream's buffer, so the stream can be dropped before them.
+///
+/// Example usage:
+/// ```
+/// // `SkinnedVertex` contains the UVs and normals, which never change
+/// let mut mesh = builder.build(&context, &program, MeshUsage::StaticDraw, DrawMode::Draw3D);
+/// let mut positions = VertexStream::from_data(&context, &bind_pose, MeshUsage::StreamDraw);
+/// mesh.attach_stream(&positions);
+/// // Every frame:
+/// positions.set_data(&skin(&bind_pose, &bones));
+/// mesh.draw(&surface, &uniforms);
+/// ```
+pub struct VertexStream<S: Vertex> {
+    // Shared with the meshes the stream is attached to
+    pub(crate) buffer: Rc<RefCell<GlBuffer>>,
+    phantom: PhantomData<S>,
+}
+
+impl<S: Vertex> VertexStream<S> {
+    /// Creates an empty `VertexStream`.
+    pub fn new(context: &GlContext, usage: MeshUsage) -> Self {
+        VertexStream {
+            buffer: Rc::new(RefCell::new(GlBuffer::new(context, WebGl2::ARRAY_BUFFER, usage))),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Creates a `VertexStream` containing the given vertices.
+    pub fn from_data(context: &GlContext, verts: &[S], usage: MeshUsage) -> Self {
+        let mut res = Self::new(context, usage);
+        res.set_data(verts);
+        res
+    }
+
+    /// Returns the number of vertices in the stream.
+    pub fn len(&self) -> usize {
+        self.buffer.borrow().len() / S::stride() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Replaces all vertices in the stream.
+    pub fn set_data(&mut self, verts: &[S]) {
+        self.buffer.borrow_mut().set_data(&vertex_bytes(verts));
+    }
+
+    /// Replaces the vertices starting at `start`. `start` can be at most `len()`; vertices past
+    /// the end are appended.
+    pub fn update(&mut self, start: usize, verts: &[S]) {
+        self.buffer.borrow_mut().write(start * S::stride() as usize, &vertex_bytes(verts));
+    }
+}
@@ -0,0 +1,195 @@
+use log::*;
+use naga::back::glsl;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use std::collections::HashMap;
+
+use crate::context::*;
+use crate::program::*;
+use crate::uniforms::*;
+use crate::vertex::*;
+
+/// Translates the given entry points of a WGSL shader to GLSL ES 3.00, returning the vertex
+/// and fragment shader sources. This requires the `wgsl` feature.
+///
+/// The generated GLSL uses names from the WGSL source wherever this crate looks things up by
+/// name:
+/// * Vertex inputs are named after the entry point's arguments, or the members of its argument
+///   structs, so they must match the names in `VertexData::ATTRIBUTES`.
+/// * Uniform blocks are named after the `var<uniform>` they come from, so they can be used with
+///   `GlProgram::bind_uniform_block`. WGSL doesn't allow uniforms outside blocks.
+/// * Each texture is combined with the sampler it's used with into a `sampler2D` named after
+///   the texture, which can be set with a `TextureUniform`. The filtering and wrapping of the
+///   `Texture2d` are used rather than the WGSL sampler's.
+///
+/// Example usage:
+/// ```
+/// // struct VertexInput {
+/// //     @location(0) pos: vec2<f32>,
+/// //     @location(1) color: vec4<f32>,
+/// // }
+/// // @group(0) @binding(0) var<uniform> camera: Camera;
+/// // @vertex fn vs_main(in: VertexInput) -> VertexOutput { ... }
+/// // @fragment fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> { ... }
+/// let program = GlProgram::<ColorVertex, ColorUniformsGl>::new_wgsl(
+///     &context,
+///     include_str!("color.wgsl"),
+///     "vs_main",
+///     "fs_main",
+/// );
+/// program.bind_uniform_block::<CameraBlock>("camera", 0);
+/// ```
+pub fn wgsl_to_glsl(
+    source: &str,
+    vert_entry_point: &str,
+    frag_entry_point: &str,
+) -> Result<(String, String), String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|err| err.emit_to_string(source))?;
+    module_to_glsl(&module, vert_entry_point, frag_entry_point)
+        .map_err(|err| format!("Error translating WGSL: {}", err))
+}
+
+/// Like `wgsl_to_glsl`, but translates a naga module that was already parsed or generated.
+pub fn module_to_glsl(
+    module: &naga::Module,
+    vert_entry_point: &str,
+    frag_entry_point: &str,
+) -> Result<(String, String), String> {
+    let info = Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(module)
+        .map_err(|err| err.as_inner().to_string())?;
+    let vert_shader = write_glsl(module, &info, naga::ShaderStage::Vertex, vert_entry_point)?;
+    let frag_shader = write_glsl(module, &info, naga::ShaderStage::Fragment, frag_entry_point)?;
+    Ok((vert_shader, frag_shader))
+}
+
+fn write_glsl(
+    module: &naga::Module,
+    info: &naga::valid::ModuleInfo,
+    stage: naga::ShaderStage,
+    entry_point: &str,
+) -> Result<String, String> {
+    let options = glsl::Options {
+        version: glsl::Version::Embedded { version: 300, is_webgl: true },
+        ..glsl::Options::default()
+    };
+    let pipeline_options = glsl::PipelineOptions {
+        shader_stage: stage,
+        entry_point: entry_point.to_string(),
+        multiview: None,
+    };
+
+    let mut source = String::new();
+    let mut writer = glsl::Writer::new(
+        &mut source,
+        module,
+        info,
+        &options,
+        &pipeline_options,
+        naga::proc::BoundsCheckPolicies::default(),
+    )
+    .map_err(|err| err.to_string())?;
+    let reflection = writer.write().map_err(|err| err.to_string())?;
+
+    let mut names = HashMap::new();
+    for (handle, block_name) in &reflection.uniforms {
+        if let Some(name) = &module.global_variables[*handle].name {
+            names.insert(block_name.clone(), name.clone());
+        }
+    }
+    for (sampler_name, mapping) in &reflection.texture_mapping {
+        if let Some(name) = &module.global_variables[mapping.texture].name {
+            names.insert(sampler_name.clone(), name.clone());
+        }
+    }
+    if stage == naga::ShaderStage::Vertex {
+        let entry_point = module
+            .entry_points
+            .iter()
+            .find(|ep| ep.stage == stage && ep.name == entry_point)
+            .ok_or_else(|| format!("Entry point {} not found", entry_point))?;
+        for (location, name) in vertex_input_names(module, &entry_point.function) {
+            names.insert(format!("_p2vs_location{}", location), name);
+        }
+    }
+
+    Ok(rename_identifiers(&source, &names))
+}
+
+/// Returns the location and name of each of the inputs to a vertex shader.
+fn vertex_input_names(module: &naga::Module, function: &naga::Function) -> Vec<(u32, String)> {
+    let mut res = vec![];
+    for arg in &function.arguments {
+        match (&arg.binding, &module.types[arg.ty].inner) {
+            (Some(naga::Binding::Location { location, .. }), _) => {
+                if let Some(name) = &arg.name {
+                    res.push((*location, name.clone()));
+                }
+            }
+            (None, naga::TypeInner::Struct { members, .. }) => {
+                for member in members {
+                    if let (Some(naga::Binding::Location { location, .. }), Some(name)) =
+                        (&member.binding, &member.name)
+                    {
+                        res.push((*location, name.clone()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+/// Replaces each identifier in `source` that's a key in `names`. Identifiers are only replaced
+/// as a whole, so `a` doesn't affect `ab`.
+fn rename_identifiers(source: &str, names: &HashMap<String, String>) -> String {
+    let is_ident_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut res = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find(is_ident_char) {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+        let ident = &rest[..end];
+        res.push_str(names.get(ident).map_or(ident, |name| name.as_str()));
+        rest = &rest[end..];
+    }
+    res.push_str(rest);
+    res
+}
+
+impl<V: Vertex, U: GlUniforms> GlProgram<V, U> {
+    /// Creates a program from WGSL source, by translating it with `wgsl_to_glsl`. This requires
+    /// the `wgsl` feature.
+    pub fn new_wgsl(
+        context: &GlContext,
+        source: &str,
+        vert_entry_point: &str,
+        frag_entry_point: &str,
+    ) -> Self {
+        match wgsl_to_glsl(source, vert_entry_point, frag_entry_point) {
+            Ok((vert_shader, frag_shader)) => Self::new(context, &vert_shader, &frag_shader),
+            Err(err) => {
+                error!("{}", err);
+                panic!();
+            }
+        }
+    }
+
+    /// Creates a program from a naga module, by translating it with `module_to_glsl`. This
+    /// requires the `wgsl` feature.
+    pub fn new_naga(
+        context: &GlContext,
+        module: &naga::Module,
+        vert_entry_point: &str,
+        frag_entry_point: &str,
+    ) -> Self {
+        match module_to_glsl(module, vert_entry_point, frag_entry_point) {
+            Ok((vert_shader, frag_shader)) => Self::new(context, &vert_shader, &frag_shader),
+            Err(err) => {
+                error!("{}", err);
+                panic!();
+            }
+        }
+    }
+}